@@ -0,0 +1,14 @@
+#![no_main]
+
+use irc::protocol::parse_msg;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(line) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(msg) = parse_msg(line) {
+        // Whatever parses has to serialize to something that parses again.
+        parse_msg(&msg.to_wire()).expect("serialized message does not parse");
+    }
+});
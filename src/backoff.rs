@@ -0,0 +1,76 @@
+/// Reconnection delays
+use std::time::Duration;
+
+/// Exponential backoff with symmetric jitter: the n-th retry waits `initial * multiplier^n`,
+/// capped at `max`, then spread by up to `jitter` (a fraction of the delay) in either direction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub multiplier: f64,
+    pub jitter: f64,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(2),
+            max: Duration::from_secs(300),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay before retry number `attempt` (starting at 0). `rand` is a sample from [0, 1).
+    pub fn delay(&self, attempt: u32, rand: f64) -> Duration {
+        let base = self.initial.as_secs_f64() * self.multiplier.powf(attempt as f64);
+        let base = base.min(self.max.as_secs_f64());
+        let spread = base * self.jitter.clamp(0.0, 1.0) * (2.0 * rand - 1.0);
+        Duration::from_secs_f64((base + spread).clamp(0.0, self.max.as_secs_f64()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_jitter() -> Backoff {
+        Backoff {
+            jitter: 0.0,
+            ..Backoff::default()
+        }
+    }
+
+    #[test]
+    fn test_delay_doubles() {
+        let backoff = no_jitter();
+        assert_eq!(backoff.delay(0, 0.3), Duration::from_secs(2));
+        assert_eq!(backoff.delay(1, 0.3), Duration::from_secs(4));
+        assert_eq!(backoff.delay(2, 0.3), Duration::from_secs(8));
+        assert_eq!(backoff.delay(5, 0.3), Duration::from_secs(64));
+    }
+
+    #[test]
+    fn test_delay_capped() {
+        let backoff = no_jitter();
+        assert_eq!(backoff.delay(9, 0.0), Duration::from_secs(300));
+        assert_eq!(backoff.delay(1000, 0.0), Duration::from_secs(300));
+    }
+
+    #[test]
+    fn test_delay_jitter_bounds() {
+        let backoff = Backoff::default();
+        assert_eq!(backoff.delay(2, 0.5), Duration::from_secs(8));
+        assert_eq!(backoff.delay(2, 0.0), Duration::from_secs_f64(6.4));
+        assert!(backoff.delay(2, 0.999) < Duration::from_secs_f64(9.6));
+        assert!(backoff.delay(2, 0.999) > Duration::from_secs_f64(9.5));
+    }
+
+    #[test]
+    fn test_delay_jitter_never_exceeds_max() {
+        let backoff = Backoff::default();
+        assert_eq!(backoff.delay(20, 0.999), Duration::from_secs(300));
+    }
+}
@@ -0,0 +1,494 @@
+/// IRCv3 capability negotiation
+///
+/// See https://ircv3.net/specs/extensions/capability-negotiation
+use crate::protocol::{ClientMsg, ServCmd};
+use crate::sasl::{Authenticator, Progress};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Capabilities requested whenever the server offers them.
+pub const WANTED: &[&str] = &["cap-notify", "multi-prefix", "server-time"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    /// Nothing sent yet.
+    Idle,
+    /// Sent `CAP LS 302`, collecting the (possibly multiline) reply.
+    Listing,
+    /// Sent `CAP REQ`, waiting for the ACK or NAK.
+    Requesting,
+    /// SASL exchange in progress, `CAP END` goes out once it is over.
+    Authenticating,
+    /// Sent `CAP END` or registered without negotiating. Only NEW/DEL and their REQs from here on.
+    Done,
+}
+
+/// Negotiation state and the record of what the server offers and what is enabled.
+#[derive(Debug)]
+pub struct Caps {
+    phase: Phase,
+    wanted: Vec<String>,
+    /// Offered by the server, with their values (`sasl=PLAIN,EXTERNAL` maps to `PLAIN,EXTERNAL`).
+    available: BTreeMap<String, Option<String>>,
+    enabled: BTreeSet<String>,
+    /// REQs sent and not yet answered.
+    pending: usize,
+    /// Authenticate before ending negotiation, if configured.
+    sasl: Option<Authenticator>,
+}
+
+impl Default for Caps {
+    fn default() -> Self {
+        Self::with_sasl(None)
+    }
+}
+
+impl Caps {
+    pub fn new(mut wanted: Vec<String>, sasl: Option<Authenticator>) -> Self {
+        if sasl.is_some() {
+            wanted.push("sasl".to_string());
+        }
+        Self {
+            phase: Phase::Idle,
+            wanted,
+            available: BTreeMap::new(),
+            enabled: BTreeSet::new(),
+            pending: 0,
+            sasl,
+        }
+    }
+
+    /// Negotiate the default capabilities, plus SASL if an authenticator is given.
+    pub fn with_sasl(sasl: Option<Authenticator>) -> Self {
+        Self::new(WANTED.iter().map(|cap| cap.to_string()).collect(), sasl)
+    }
+
+    pub fn is_enabled(&self, cap: &str) -> bool {
+        self.enabled.contains(cap)
+    }
+
+    pub fn enabled(&self) -> impl Iterator<Item = &str> {
+        self.enabled.iter().map(|cap| cap.as_str())
+    }
+
+    /// Open negotiation. Must be sent before NICK/USER so the server holds registration.
+    pub fn start(&mut self) -> Vec<ClientMsg> {
+        self.phase = Phase::Listing;
+        vec![ClientMsg::CapLs]
+    }
+
+    /// Advance the state machine on a server message and return the lines to send in response.
+    pub fn handle(&mut self, cmd: &ServCmd) -> Vec<ClientMsg> {
+        if self.phase == Phase::Authenticating {
+            if let Some(progress) = self.sasl.as_mut().and_then(|sasl| sasl.handle(cmd)) {
+                return self.on_progress(progress);
+            }
+        }
+        match cmd {
+            ServCmd::Cap { subcmd, more, caps } => match subcmd.as_str() {
+                "LS" => self.on_ls(*more, caps),
+                "ACK" => {
+                    for cap in caps {
+                        match cap.strip_prefix('-') {
+                            Some(cap) => self.enabled.remove(cap),
+                            None => self.enabled.insert(cap.clone()),
+                        };
+                    }
+                    self.on_reply()
+                }
+                "NAK" => self.on_reply(),
+                "NEW" => {
+                    self.add_available(caps);
+                    let names = caps.iter().map(|cap| cap_name(cap)).collect::<Vec<_>>();
+                    self.request(&names)
+                }
+                "DEL" => {
+                    for cap in caps {
+                        self.available.remove(cap.as_str());
+                        self.enabled.remove(cap.as_str());
+                    }
+                    vec![]
+                }
+                _ => vec![],
+            },
+            // The server ignored CAP LS (no IRCv3 support) and registered us anyway.
+            ServCmd::RplWelcome { .. } => {
+                let skipped = self.phase != Phase::Done;
+                self.phase = Phase::Done;
+                match &self.sasl {
+                    Some(sasl) if skipped && sasl.is_required() => vec![ClientMsg::Quit {
+                        msg: "SASL authentication failed: server registered us without it"
+                            .to_string(),
+                    }],
+                    _ => vec![],
+                }
+            }
+            _ => vec![],
+        }
+    }
+
+    fn on_ls(&mut self, more: bool, caps: &[String]) -> Vec<ClientMsg> {
+        self.add_available(caps);
+        if more || self.phase != Phase::Listing {
+            return vec![];
+        }
+        let names = self.available.keys().cloned().collect::<Vec<_>>();
+        let out = self.request(&names);
+        if out.is_empty() {
+            self.requests_done()
+        } else {
+            self.phase = Phase::Requesting;
+            out
+        }
+    }
+
+    fn on_reply(&mut self) -> Vec<ClientMsg> {
+        self.pending = self.pending.saturating_sub(1);
+        if self.phase == Phase::Requesting && self.pending == 0 {
+            self.requests_done()
+        } else {
+            vec![]
+        }
+    }
+
+    /// All initial REQs are answered: authenticate if we can, otherwise wrap up.
+    fn requests_done(&mut self) -> Vec<ClientMsg> {
+        let offered = self.available.get("sasl").cloned().flatten();
+        let Some(sasl) = self.sasl.as_mut() else {
+            return self.end();
+        };
+        if !self.enabled.contains("sasl") {
+            return self.on_progress(Progress::Failure(
+                "server does not support SASL".to_string(),
+            ));
+        }
+        let progress = sasl.start(offered.as_deref());
+        self.phase = Phase::Authenticating;
+        self.on_progress(progress)
+    }
+
+    fn on_progress(&mut self, progress: Progress) -> Vec<ClientMsg> {
+        match progress {
+            Progress::Continue(out) => out,
+            Progress::Success => self.end(),
+            Progress::Failure(reason) if self.sasl.as_ref().is_some_and(|s| s.is_required()) => {
+                self.phase = Phase::Done;
+                vec![ClientMsg::Quit {
+                    msg: format!("SASL authentication failed: {reason}"),
+                }]
+            }
+            Progress::Failure(_) => self.end(),
+        }
+    }
+
+    fn end(&mut self) -> Vec<ClientMsg> {
+        self.phase = Phase::Done;
+        vec![ClientMsg::CapEnd]
+    }
+
+    fn add_available(&mut self, caps: &[String]) {
+        for cap in caps {
+            let (name, value) = match cap.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (cap.as_str(), None),
+            };
+            self.available.insert(name.to_string(), value);
+        }
+    }
+
+    /// REQ the wanted capabilities among `offered` that aren't enabled yet.
+    fn request<S: AsRef<str>>(&mut self, offered: &[S]) -> Vec<ClientMsg> {
+        let caps = offered
+            .iter()
+            .map(|cap| cap.as_ref())
+            .filter(|cap| self.wanted.iter().any(|w| w == cap) && !self.is_enabled(cap))
+            .map(|cap| cap.to_string())
+            .collect::<Vec<_>>();
+        if caps.is_empty() {
+            return vec![];
+        }
+        self.pending += 1;
+        vec![ClientMsg::CapReq { caps }]
+    }
+}
+
+fn cap_name(cap: &str) -> &str {
+    cap.split_once('=').map_or(cap, |(name, _)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::parse_msg;
+    use crate::sasl::{Mechanism, SaslConfig};
+
+    /// Feed server lines through the state machine and collect everything it sends back.
+    fn run(caps: &mut Caps, transcript: &[&str]) -> Vec<String> {
+        transcript
+            .iter()
+            .flat_map(|line| caps.handle(&parse_msg(line).unwrap().command))
+            .map(|msg| msg.to_wire().unwrap())
+            .collect()
+    }
+
+    fn wanting(wanted: &[&str]) -> Caps {
+        Caps::new(wanted.iter().map(|cap| cap.to_string()).collect(), None)
+    }
+
+    fn wanting_sasl(wanted: &[&str], mech: Mechanism, required: bool) -> Caps {
+        let sasl = Authenticator::new(SaslConfig { mech, required });
+        Caps::new(
+            wanted.iter().map(|cap| cap.to_string()).collect(),
+            Some(sasl),
+        )
+    }
+
+    fn plain() -> Mechanism {
+        Mechanism::Plain {
+            user: "MrAccount".to_string(),
+            pass: "hunter2".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_negotiate_ack() {
+        let mut caps = wanting(&["multi-prefix", "sasl"]);
+        assert_eq!(caps.start(), vec![ClientMsg::CapLs]);
+        assert_ne!(caps.phase, Phase::Done);
+
+        let out = run(
+            &mut caps,
+            &[":irc.example.com CAP * LS :multi-prefix sasl=PLAIN,EXTERNAL away-notify"],
+        );
+        assert_eq!(out, vec!["CAP REQ :multi-prefix sasl"]);
+        assert_ne!(caps.phase, Phase::Done);
+
+        let out = run(
+            &mut caps,
+            &[":irc.example.com CAP * ACK :multi-prefix sasl"],
+        );
+        assert_eq!(out, vec!["CAP END"]);
+        assert_eq!(caps.phase, Phase::Done);
+        assert!(caps.is_enabled("sasl"));
+        assert!(caps.is_enabled("multi-prefix"));
+        assert!(!caps.is_enabled("away-notify"));
+        assert_eq!(caps.available["sasl"].as_deref(), Some("PLAIN,EXTERNAL"));
+        assert_eq!(caps.available["multi-prefix"], None);
+    }
+
+    #[test]
+    fn test_negotiate_multiline_ls() {
+        let mut caps = wanting(&["multi-prefix", "server-time"]);
+        caps.start();
+        let out = run(
+            &mut caps,
+            &[
+                ":irc.example.com CAP * LS * :multi-prefix extended-join",
+                ":irc.example.com CAP * LS * :account-notify batch",
+            ],
+        );
+        assert!(out.is_empty());
+        let out = run(&mut caps, &[":irc.example.com CAP * LS :server-time"]);
+        assert_eq!(out, vec!["CAP REQ :multi-prefix server-time"]);
+    }
+
+    #[test]
+    fn test_negotiate_nak() {
+        let mut caps = wanting(&["multi-prefix"]);
+        caps.start();
+        let out = run(
+            &mut caps,
+            &[
+                ":irc.example.com CAP * LS :multi-prefix",
+                ":irc.example.com CAP * NAK :multi-prefix",
+            ],
+        );
+        assert_eq!(out, vec!["CAP REQ :multi-prefix", "CAP END"]);
+        assert!(!caps.is_enabled("multi-prefix"));
+        assert_eq!(caps.phase, Phase::Done);
+    }
+
+    #[test]
+    fn test_negotiate_nothing_wanted() {
+        let mut caps = wanting(&["sasl"]);
+        caps.start();
+        let out = run(&mut caps, &[":irc.example.com CAP * LS :away-notify"]);
+        assert_eq!(out, vec!["CAP END"]);
+        assert_eq!(caps.phase, Phase::Done);
+    }
+
+    #[test]
+    fn test_server_without_cap() {
+        let mut caps = wanting(&["sasl"]);
+        caps.start();
+        let out = run(
+            &mut caps,
+            &[":irc.example.com 001 MrNickname :Welcome to the network MrNickname"],
+        );
+        assert!(out.is_empty());
+        assert_eq!(caps.phase, Phase::Done);
+        assert_eq!(caps.enabled().count(), 0);
+    }
+
+    #[test]
+    fn test_new_and_del_after_registration() {
+        let mut caps = wanting(&["multi-prefix", "sasl"]);
+        caps.start();
+        let out = run(
+            &mut caps,
+            &[
+                ":irc.example.com CAP * LS :multi-prefix cap-notify",
+                ":irc.example.com CAP MrNickname ACK :multi-prefix",
+                ":irc.example.com 001 MrNickname :Welcome",
+                ":irc.example.com CAP MrNickname NEW :sasl=PLAIN batch",
+            ],
+        );
+        assert_eq!(
+            out,
+            vec!["CAP REQ :multi-prefix", "CAP END", "CAP REQ :sasl"]
+        );
+
+        // A late ACK must not end negotiation a second time.
+        let out = run(&mut caps, &[":irc.example.com CAP MrNickname ACK :sasl"]);
+        assert!(out.is_empty());
+        assert!(caps.is_enabled("sasl"));
+        assert_eq!(caps.available["sasl"].as_deref(), Some("PLAIN"));
+
+        let out = run(&mut caps, &[":irc.example.com CAP MrNickname DEL :sasl"]);
+        assert!(out.is_empty());
+        assert!(!caps.is_enabled("sasl"));
+        assert!(!caps.available.contains_key("sasl"));
+        assert_eq!(caps.enabled().collect::<Vec<_>>(), vec!["multi-prefix"]);
+    }
+
+    #[test]
+    fn test_ack_disable() {
+        let mut caps = wanting(&["multi-prefix"]);
+        caps.start();
+        run(
+            &mut caps,
+            &[
+                ":irc.example.com CAP * LS :multi-prefix",
+                ":irc.example.com CAP * ACK :multi-prefix",
+                ":irc.example.com CAP MrNickname ACK :-multi-prefix",
+            ],
+        );
+        assert!(!caps.is_enabled("multi-prefix"));
+    }
+
+    #[test]
+    fn test_sasl_plain_before_end() {
+        let mut caps = wanting_sasl(&["multi-prefix"], plain(), false);
+        caps.start();
+        let out = run(
+            &mut caps,
+            &[
+                ":irc.example.com CAP * LS :multi-prefix sasl=PLAIN,EXTERNAL",
+                ":irc.example.com CAP * ACK :multi-prefix sasl",
+                "AUTHENTICATE +",
+                ":irc.example.com 900 MrNickname MrNickname!~u@h MrAccount :You are now logged in as MrAccount",
+            ],
+        );
+        assert_eq!(
+            out,
+            vec![
+                "CAP REQ :multi-prefix sasl",
+                "AUTHENTICATE PLAIN",
+                "AUTHENTICATE TXJBY2NvdW50AE1yQWNjb3VudABodW50ZXIy",
+            ]
+        );
+        assert_eq!(caps.phase, Phase::Authenticating);
+
+        let out = run(
+            &mut caps,
+            &[":irc.example.com 903 MrNickname :SASL authentication successful"],
+        );
+        assert_eq!(out, vec!["CAP END"]);
+        assert_eq!(caps.phase, Phase::Done);
+    }
+
+    #[test]
+    fn test_sasl_failure_continues() {
+        let mut caps = wanting_sasl(&[], Mechanism::External, false);
+        caps.start();
+        let out = run(
+            &mut caps,
+            &[
+                ":irc.example.com CAP * LS :sasl",
+                ":irc.example.com CAP * ACK :sasl",
+                "AUTHENTICATE +",
+                ":irc.example.com 904 MrNickname :SASL authentication failed",
+            ],
+        );
+        assert_eq!(
+            out,
+            vec![
+                "CAP REQ :sasl",
+                "AUTHENTICATE EXTERNAL",
+                "AUTHENTICATE +",
+                "CAP END",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sasl_failure_required_quits() {
+        let mut caps = wanting_sasl(&[], plain(), true);
+        caps.start();
+        let out = run(
+            &mut caps,
+            &[
+                ":irc.example.com CAP * LS :sasl",
+                ":irc.example.com CAP * ACK :sasl",
+                "AUTHENTICATE +",
+                ":irc.example.com 904 MrNickname :SASL authentication failed",
+            ],
+        );
+        assert_eq!(
+            out.last().unwrap(),
+            "QUIT :SASL authentication failed: SASL authentication failed"
+        );
+        assert!(!out.contains(&"CAP END".to_string()));
+    }
+
+    #[test]
+    fn test_sasl_not_offered_required_quits() {
+        let mut caps = wanting_sasl(&[], plain(), true);
+        caps.start();
+        let out = run(&mut caps, &[":irc.example.com CAP * LS :multi-prefix"]);
+        assert_eq!(
+            out,
+            vec!["QUIT :SASL authentication failed: server does not support SASL"]
+        );
+    }
+
+    #[test]
+    fn test_sasl_cap_ignored_required_quits() {
+        let mut caps = wanting_sasl(&[], plain(), true);
+        caps.start();
+        let out = run(&mut caps, &[":irc.example.com 001 MrNickname :Welcome"]);
+        assert_eq!(
+            out,
+            vec!["QUIT :SASL authentication failed: server registered us without it"]
+        );
+
+        let mut caps = wanting_sasl(&[], plain(), false);
+        caps.start();
+        let out = run(&mut caps, &[":irc.example.com 001 MrNickname :Welcome"]);
+        assert!(out.is_empty());
+        assert_eq!(caps.phase, Phase::Done);
+    }
+
+    #[test]
+    fn test_sasl_mechanism_not_offered() {
+        let mut caps = wanting_sasl(&[], plain(), false);
+        caps.start();
+        let out = run(
+            &mut caps,
+            &[
+                ":irc.example.com CAP * LS :sasl=EXTERNAL",
+                ":irc.example.com CAP * ACK :sasl",
+            ],
+        );
+        assert_eq!(out, vec!["CAP REQ :sasl", "CAP END"]);
+    }
+}
@@ -0,0 +1,81 @@
+/// How a server folds the case of nicks and channel names.
+/// See https://modern.ircdocs.horse/#casemapping-parameter
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CaseMapping {
+    /// Only `A-Z`.
+    Ascii,
+    /// `A-Z`, and `[]\~` as the upper case of `{}|^`. What servers use if they don't say.
+    #[default]
+    Rfc1459,
+    /// Like `Rfc1459` without `~` and `^`.
+    StrictRfc1459,
+}
+
+impl CaseMapping {
+    /// From a CASEMAPPING value. Mappings we don't know fold at least ASCII.
+    pub fn from_isupport(value: &str) -> Self {
+        match value {
+            "rfc1459" => CaseMapping::Rfc1459,
+            "strict-rfc1459" => CaseMapping::StrictRfc1459,
+            _ => CaseMapping::Ascii,
+        }
+    }
+
+    fn fold_char(self, c: char) -> char {
+        match (self, c) {
+            (_, 'A'..='Z') => c.to_ascii_lowercase(),
+            (CaseMapping::Ascii, _) => c,
+            (_, '[') => '{',
+            (_, ']') => '}',
+            (_, '\\') => '|',
+            (CaseMapping::Rfc1459, '~') => '^',
+            _ => c,
+        }
+    }
+
+    /// The lower case form, for use as a key.
+    pub fn fold(self, s: &str) -> String {
+        s.chars().map(|c| self.fold_char(c)).collect()
+    }
+
+    pub fn eq(self, a: &str, b: &str) -> bool {
+        a.chars()
+            .map(|c| self.fold_char(c))
+            .eq(b.chars().map(|c| self.fold_char(c)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fold() {
+        assert_eq!(CaseMapping::Ascii.fold("Nick[]\\~"), "nick[]\\~");
+        assert_eq!(CaseMapping::Rfc1459.fold("Nick[]\\~"), "nick{}|^");
+        assert_eq!(CaseMapping::StrictRfc1459.fold("Nick[]\\~"), "nick{}|~");
+        // Only ASCII, whatever the mapping.
+        assert_eq!(CaseMapping::Rfc1459.fold("ÉCOLE"), "École");
+    }
+
+    #[test]
+    fn test_eq() {
+        assert!(CaseMapping::Ascii.eq("#Rust", "#rust"));
+        assert!(!CaseMapping::Ascii.eq("nick[a]", "nick{a}"));
+        assert!(CaseMapping::Rfc1459.eq("nick[a]", "NICK{A}"));
+        assert!(CaseMapping::Rfc1459.eq("a~", "a^"));
+        assert!(!CaseMapping::StrictRfc1459.eq("a~", "a^"));
+        assert!(!CaseMapping::Rfc1459.eq("nick", "nick_"));
+    }
+
+    #[test]
+    fn test_from_isupport() {
+        assert_eq!(CaseMapping::from_isupport("ascii"), CaseMapping::Ascii);
+        assert_eq!(CaseMapping::from_isupport("rfc1459"), CaseMapping::Rfc1459);
+        assert_eq!(
+            CaseMapping::from_isupport("strict-rfc1459"),
+            CaseMapping::StrictRfc1459
+        );
+        assert_eq!(CaseMapping::from_isupport("rfc7613"), CaseMapping::Ascii);
+    }
+}
@@ -0,0 +1,463 @@
+/// Per-channel state: members, topic and modes
+use crate::casemap::CaseMapping;
+use crate::mode::{chan_changes, ArgKind, ChanModes, ModeChange};
+use crate::protocol::{Prefix, ServCmd, ServMsg};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Topic {
+    pub text: String,
+    /// Who set it, as a nick or a full `nick!user@host`, once the server told us.
+    pub setter: Option<String>,
+    pub set_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub name: String,
+    /// Folded nick to the nick and its member prefixes, highest rank first (`@+` for an op with
+    /// voice).
+    members: BTreeMap<String, (String, String)>,
+    /// Members from 353 replies, until the 366 that ends them.
+    names: Option<BTreeMap<String, (String, String)>>,
+    pub topic: Option<Topic>,
+    /// Channel modes and their arguments. List modes (bans and the like) aren't tracked.
+    modes: BTreeMap<char, Option<String>>,
+    /// When we joined.
+    pub joined: DateTime<Utc>,
+    casemapping: CaseMapping,
+}
+
+impl Channel {
+    fn new(name: &str, joined: DateTime<Utc>, casemapping: CaseMapping) -> Self {
+        Self {
+            name: name.to_string(),
+            members: BTreeMap::new(),
+            names: None,
+            topic: None,
+            modes: BTreeMap::new(),
+            joined,
+            casemapping,
+        }
+    }
+
+    /// Nicks with their highest prefix, if any, in case-insensitive order.
+    pub fn members(&self) -> impl Iterator<Item = (&str, Option<char>)> {
+        self.members
+            .values()
+            .map(|(nick, prefixes)| (nick.as_str(), prefixes.chars().next()))
+    }
+
+    /// The highest prefix of a member.
+    pub fn prefix(&self, nick: &str) -> Option<char> {
+        let (_, prefixes) = self.members.get(&self.casemapping.fold(nick))?;
+        prefixes.chars().next()
+    }
+
+    fn add_member(&mut self, nick: &str, prefixes: String) {
+        let key = self.casemapping.fold(nick);
+        self.members.insert(key, (nick.to_string(), prefixes));
+    }
+
+    /// Returns whether `nick` was here.
+    fn remove_member(&mut self, nick: &str) -> bool {
+        self.members.remove(&self.casemapping.fold(nick)).is_some()
+    }
+
+    fn set_casemapping(&mut self, casemapping: CaseMapping) {
+        self.casemapping = casemapping;
+        self.members = refold(std::mem::take(&mut self.members), casemapping);
+        self.names = self.names.take().map(|names| refold(names, casemapping));
+    }
+
+    /// Modes in `+ntk key` form.
+    pub fn modes(&self) -> String {
+        let letters = self.modes.keys().collect::<String>();
+        let args = self.modes.values().flatten();
+        let mut modes = format!("+{letters}");
+        for arg in args {
+            modes.push(' ');
+            modes.push_str(arg);
+        }
+        modes
+    }
+
+    /// Returns whether `old` was here.
+    fn rename(&mut self, old: &str, new: &str) -> bool {
+        match self.members.remove(&self.casemapping.fold(old)) {
+            Some((_, prefixes)) => {
+                self.add_member(new, prefixes);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn set_prefix(&mut self, nick: &str, prefix: char, set: bool, chanmodes: &ChanModes) {
+        let Some((_, prefixes)) = self.members.get_mut(&self.casemapping.fold(nick)) else {
+            return;
+        };
+        let mut has = prefixes
+            .chars()
+            .filter(|&p| p != prefix)
+            .collect::<String>();
+        if set {
+            has.push(prefix);
+        }
+        *prefixes = chanmodes.prefixes().filter(|p| has.contains(*p)).collect();
+    }
+
+    fn apply_modes(&mut self, changes: &[ModeChange], chanmodes: &ChanModes) {
+        for change in changes {
+            let ModeChange {
+                set,
+                mode,
+                kind,
+                arg,
+            } = change;
+            match kind {
+                ArgKind::Prefix(prefix) => {
+                    if let Some(nick) = arg {
+                        self.set_prefix(nick, *prefix, *set, chanmodes);
+                    }
+                }
+                ArgKind::List => {}
+                ArgKind::Always | ArgKind::WhenSet | ArgKind::Never => {
+                    if *set {
+                        self.modes.insert(*mode, arg.clone());
+                    } else {
+                        self.modes.remove(mode);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Key members by their nick folded with `casemapping`.
+fn refold(
+    members: BTreeMap<String, (String, String)>,
+    casemapping: CaseMapping,
+) -> BTreeMap<String, (String, String)> {
+    members
+        .into_values()
+        .map(|member| (casemapping.fold(&member.0), member))
+        .collect()
+}
+
+/// Split a NAMES entry like `@+nick` into its prefixes and the nick.
+fn split_prefixes<'a>(entry: &'a str, chanmodes: &ChanModes) -> (String, &'a str) {
+    let nick = entry.trim_start_matches(|c| chanmodes.is_prefix(c));
+    let prefixes = entry[..entry.len() - nick.len()].to_string();
+    (prefixes, nick)
+}
+
+/// The channels we're in.
+#[derive(Debug, Default)]
+pub struct Channels {
+    /// By folded name
+    chans: BTreeMap<String, Channel>,
+    casemapping: CaseMapping,
+}
+
+impl Channels {
+    pub fn get(&self, name: &str) -> Option<&Channel> {
+        self.chans.get(&self.casemapping.fold(name))
+    }
+
+    fn get_mut(&mut self, name: &str) -> Option<&mut Channel> {
+        self.chans.get_mut(&self.casemapping.fold(name))
+    }
+
+    /// The server told us how it folds case. Normally before we join anything, but just in case.
+    pub fn set_casemapping(&mut self, casemapping: CaseMapping) {
+        if casemapping == self.casemapping {
+            return;
+        }
+        self.casemapping = casemapping;
+        self.chans = std::mem::take(&mut self.chans)
+            .into_values()
+            .map(|mut chan| {
+                chan.set_casemapping(casemapping);
+                (casemapping.fold(&chan.name), chan)
+            })
+            .collect();
+    }
+
+    /// Update from a message, `own_nick` being our current nick. Returns the channels a NICK or
+    /// QUIT touched, which can't be told from the state afterwards.
+    pub fn handle(
+        &mut self,
+        own_nick: &str,
+        chanmodes: &ChanModes,
+        msg: &ServMsg,
+        time: DateTime<Utc>,
+    ) -> Vec<String> {
+        let nick = match &msg.prefix {
+            Some(Prefix::User { nick, .. }) => Some(nick.as_str()),
+            _ => None,
+        };
+        match &msg.command {
+            ServCmd::Join { chan } => {
+                let Some(nick) = nick else { return vec![] };
+                if self.casemapping.eq(nick, own_nick) {
+                    let key = self.casemapping.fold(chan);
+                    let chan = Channel::new(chan, time, self.casemapping);
+                    self.chans.insert(key, chan);
+                }
+                if let Some(chan) = self.get_mut(chan) {
+                    chan.add_member(nick, String::new());
+                }
+            }
+            ServCmd::Part { chan, .. } => {
+                let Some(nick) = nick else { return vec![] };
+                self.leave(own_nick, chan, nick);
+            }
+            ServCmd::Kick { chan, nick, .. } => self.leave(own_nick, chan, nick),
+            ServCmd::Quit { .. } => {
+                let Some(nick) = nick else { return vec![] };
+                return self
+                    .chans
+                    .values_mut()
+                    .filter_map(|chan| chan.remove_member(nick).then(|| chan.name.clone()))
+                    .collect();
+            }
+            ServCmd::Nick { nick: new } => {
+                let Some(old) = nick else { return vec![] };
+                return self
+                    .chans
+                    .values_mut()
+                    .filter_map(|chan| chan.rename(old, new).then(|| chan.name.clone()))
+                    .collect();
+            }
+            ServCmd::Mode {
+                target,
+                modes,
+                args,
+            } => {
+                if let Some(chan) = self.get_mut(target) {
+                    chan.apply_modes(&chan_changes(modes, args, chanmodes), chanmodes);
+                }
+            }
+            ServCmd::RplChannelModeIs { chan, modes, args } => {
+                if let Some(chan) = self.get_mut(chan) {
+                    chan.modes.clear();
+                    chan.apply_modes(&chan_changes(modes, args, chanmodes), chanmodes);
+                }
+            }
+            ServCmd::NameReply { chan, nicks, .. } => {
+                if let Some(chan) = self.get_mut(chan) {
+                    let casemapping = chan.casemapping;
+                    let names = chan.names.get_or_insert_with(BTreeMap::new);
+                    for entry in nicks {
+                        let (prefixes, nick) = split_prefixes(entry, chanmodes);
+                        names.insert(casemapping.fold(nick), (nick.to_string(), prefixes));
+                    }
+                }
+            }
+            ServCmd::EndOfNames { chan, .. } => {
+                if let Some(chan) = self.get_mut(chan) {
+                    if let Some(names) = chan.names.take() {
+                        chan.members = names;
+                    }
+                }
+            }
+            ServCmd::Topic { chan, topic } => {
+                if let Some(chan) = self.get_mut(chan) {
+                    chan.topic = Some(Topic {
+                        text: topic.clone(),
+                        setter: nick.map(|nick| nick.to_string()),
+                        set_at: Some(time),
+                    });
+                }
+            }
+            ServCmd::RplTopic { chan, topic } => {
+                if let Some(chan) = self.get_mut(chan) {
+                    chan.topic = Some(Topic {
+                        text: topic.clone(),
+                        setter: None,
+                        set_at: None,
+                    });
+                }
+            }
+            ServCmd::RplTopicWhoTime {
+                chan,
+                setter,
+                set_at,
+            } => {
+                let topic = self.get_mut(chan).and_then(|chan| chan.topic.as_mut());
+                if let Some(topic) = topic {
+                    topic.setter = Some(setter.clone());
+                    topic.set_at = DateTime::from_timestamp(*set_at, 0);
+                }
+            }
+            _ => {}
+        }
+        vec![]
+    }
+
+    fn leave(&mut self, own_nick: &str, chan: &str, nick: &str) {
+        if self.casemapping.eq(nick, own_nick) {
+            self.chans.remove(&self.casemapping.fold(chan));
+        } else if let Some(chan) = self.get_mut(chan) {
+            chan.remove_member(nick);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::parse_msg;
+
+    const ME: &str = "MrNickname";
+
+    fn handle(chans: &mut Channels, lines: &[&str]) {
+        for line in lines {
+            let msg = parse_msg(line).unwrap();
+            chans.handle(ME, &ChanModes::default(), &msg, DateTime::UNIX_EPOCH);
+        }
+    }
+
+    fn joined() -> Channels {
+        let mut chans = Channels::default();
+        handle(
+            &mut chans,
+            &[
+                ":MrNickname!~guest@host JOIN #bobcat",
+                ":srv 353 MrNickname = #bobcat :@+op MrNickname",
+                ":srv 353 MrNickname = #bobcat :+bobcatLover DogPerson",
+                ":srv 366 MrNickname #bobcat :End of /NAMES list.",
+            ],
+        );
+        chans
+    }
+
+    fn members(chans: &Channels) -> Vec<(&str, Option<char>)> {
+        chans.get("#bobcat").unwrap().members().collect()
+    }
+
+    #[test]
+    fn test_names() {
+        let chans = joined();
+        assert_eq!(
+            members(&chans),
+            vec![
+                ("bobcatLover", Some('+')),
+                ("DogPerson", None),
+                ("MrNickname", None),
+                ("op", Some('@')),
+            ]
+        );
+        assert_eq!(chans.get("#elsewhere").map(|chan| chan.name.clone()), None);
+    }
+
+    #[test]
+    fn test_join_part_quit_kick() {
+        let mut chans = joined();
+        handle(
+            &mut chans,
+            &[
+                ":newbie!~n@host JOIN #bobcat",
+                ":DogPerson!~d@host PART #bobcat :bye",
+                ":op!~op@host KICK #bobcat newbie :no",
+            ],
+        );
+        let quit = parse_msg(":bobcatLover!~b@host QUIT :Quit: Leaving").unwrap();
+        assert_eq!(
+            chans.handle(ME, &ChanModes::default(), &quit, DateTime::UNIX_EPOCH),
+            vec!["#bobcat"]
+        );
+        assert!(chans
+            .handle(ME, &ChanModes::default(), &quit, DateTime::UNIX_EPOCH)
+            .is_empty());
+        assert_eq!(
+            members(&chans),
+            vec![("MrNickname", None), ("op", Some('@'))]
+        );
+        // Someone else's JOIN to a channel we're not in.
+        handle(&mut chans, &[":newbie!~n@host JOIN #elsewhere"]);
+        assert!(chans.get("#elsewhere").is_none());
+        handle(&mut chans, &[":op!~op@host KICK #bobcat MrNickname"]);
+        assert!(chans.get("#bobcat").is_none());
+    }
+
+    #[test]
+    fn test_nick() {
+        let mut chans = joined();
+        let msg = parse_msg(":op!~op@host NICK :boss").unwrap();
+        assert_eq!(
+            chans.handle(ME, &ChanModes::default(), &msg, DateTime::UNIX_EPOCH),
+            vec!["#bobcat"]
+        );
+        let chan = chans.get("#bobcat").unwrap();
+        assert!(chan.members().all(|(nick, _)| nick != "op"));
+        assert_eq!(chan.prefix("boss"), Some('@'));
+    }
+
+    #[test]
+    fn test_casemapping() {
+        let mut chans = joined();
+        chans.set_casemapping(CaseMapping::Ascii);
+        handle(
+            &mut chans,
+            &[
+                ":Op!~op@host MODE #BOBCAT +v DOGPERSON",
+                ":dogperson!~d@host NICK :Dog[1]",
+                ":mrnickname!~guest@host JOIN #Rust",
+            ],
+        );
+        assert_eq!(chans.get("#Bobcat").unwrap().prefix("dog[1]"), Some('+'));
+        assert_eq!(chans.get("#Bobcat").unwrap().prefix("dog{1}"), None);
+        assert!(chans.get("#rust").is_some());
+
+        chans.set_casemapping(CaseMapping::Rfc1459);
+        assert_eq!(chans.get("#Bobcat").unwrap().prefix("dog{1}"), Some('+'));
+        assert_eq!(chans.get("#bobcat").unwrap().name, "#bobcat");
+        handle(&mut chans, &[":MRNICKNAME!~guest@host PART #RUST"]);
+        assert!(chans.get("#rust").is_none());
+    }
+
+    #[test]
+    fn test_mode() {
+        let mut chans = joined();
+        handle(
+            &mut chans,
+            &[
+                ":op!~op@host MODE #bobcat +vo-v+ntk DogPerson DogPerson bobcatLover secret",
+                ":op!~op@host MODE #bobcat +lb-o 10 *!*@spam op",
+            ],
+        );
+        let chan = chans.get("#bobcat").unwrap();
+        assert_eq!(chan.prefix("DogPerson"), Some('@'));
+        assert_eq!(chan.prefix("bobcatLover"), None);
+        assert_eq!(chan.prefix("op"), Some('+'));
+        assert_eq!(chan.modes(), "+klnt secret 10");
+
+        handle(&mut chans, &[":srv 324 MrNickname #bobcat +n"]);
+        assert_eq!(chans.get("#bobcat").unwrap().modes(), "+n");
+    }
+
+    #[test]
+    fn test_topic() {
+        let mut chans = joined();
+        handle(
+            &mut chans,
+            &[
+                ":srv 332 MrNickname #bobcat :Bobcats only",
+                ":srv 333 MrNickname #bobcat op!~op@host 1700000000",
+            ],
+        );
+        let topic = chans.get("#bobcat").unwrap().topic.clone().unwrap();
+        assert_eq!(topic.text, "Bobcats only");
+        assert_eq!(topic.setter.as_deref(), Some("op!~op@host"));
+        assert_eq!(topic.set_at, DateTime::from_timestamp(1700000000, 0));
+
+        handle(&mut chans, &[":op!~op@host TOPIC #bobcat :Dogs too"]);
+        let topic = chans.get("#bobcat").unwrap().topic.clone().unwrap();
+        assert_eq!(topic.text, "Dogs too");
+        assert_eq!(topic.setter.as_deref(), Some("op"));
+        assert_eq!(topic.set_at, Some(DateTime::UNIX_EPOCH));
+    }
+}
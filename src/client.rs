@@ -1,35 +1,185 @@
-use crate::protocol::{parse_msg, MsgTarget, Prefix, ServCmd, ServMsg};
+use crate::backoff::Backoff;
+use crate::cap::Caps;
+use crate::casemap::CaseMapping;
+use crate::channel::{Channel, Channels};
+use crate::flood::{Flood, SendQueue};
+use crate::isupport::ISupport;
+use crate::keepalive::{Keepalive, Pinger, Tick};
+use crate::mode::Mode;
+use crate::nick::Nicks;
+use crate::protocol::{
+    parse_msg, privmsg_budget, split_message, ClientMsg, MsgTarget, Prefix, SerializeError,
+    ServCmd, ServMsg, MAX_LINE_LEN,
+};
+use crate::sasl::{Authenticator, SaslConfig};
+use crate::tls::{self, TlsState};
 use crate::ui::UI;
+use chrono::Utc;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 use tokio::io;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc::{Receiver, Sender};
 
 #[derive(Debug)]
 pub enum Event {
-    Msg { msg: ServMsg },
-    Disconnected,
+    Connected {
+        tls: TlsState,
+    },
+    ConnectFailed {
+        err: String,
+    },
+    Msg {
+        msg: ServMsg,
+        /// Channels a NICK or QUIT touched.
+        chans: Vec<String>,
+    },
+    Disconnected {
+        reason: String,
+    },
+    Reconnecting {
+        attempt: u32,
+        delay: Duration,
+    },
+    /// Messages held back by flood control.
+    Queued {
+        len: usize,
+    },
+    /// Round trip time of our last ping.
+    Lag {
+        lag: Duration,
+    },
 }
 
 #[derive(Debug)]
 pub struct ServInfo {
+    /// Tells this connection apart from the others, even ones to the same server.
+    pub id: String,
+    /// What to call the server instead of its network name.
+    pub name: Option<String>,
     pub addr: String,
     pub port: u16,
+    pub tls: bool,
+    /// Verify the server certificate. Only meaningful with `tls`.
+    pub tls_verify: bool,
+    /// PEM file with a client certificate and its private key, for SASL EXTERNAL.
+    pub tls_cert: Option<PathBuf>,
+    pub sasl: Option<SaslConfig>,
     pub nick: String,
+    /// Nicks to try, in order, if `nick` is taken.
+    pub alt_nicks: Vec<String>,
     pub user: String,
     pub real: String,
+    pub backoff: Backoff,
+    pub flood: Flood,
+    pub keepalive: Keepalive,
 }
 
-impl ServInfo {
-    pub fn name(&self) -> &str {
-        &self.addr
+/// Commands from the app to the network loop.
+#[derive(Debug)]
+enum NetCmd {
+    /// Queue these messages, in order. A long message split in pieces goes as one command so
+    /// that it can't fill the channel.
+    Send(Vec<ClientMsg>),
+    /// Drop the current connection, or skip the backoff delay, and connect again right away.
+    Reconnect,
+}
+
+/// Connection state shared by the UI, the event handler and the network loop.
+#[derive(Debug)]
+pub struct State {
+    pub cur_nick: String,
+    pub caps: Caps,
+    /// Our user name as others see it, a pessimistic guess until the server echoes it back.
+    pub user: String,
+    /// Our host as others see it, once the server told us.
+    pub host: Option<String>,
+    pub chans: Channels,
+    /// What the server told us it supports.
+    pub isupport: ISupport,
+    /// Keys we joined channels with, for rejoining. By name in ASCII lower case, since
+    /// CASEMAPPING isn't known yet when rejoining.
+    keys: BTreeMap<String, String>,
+}
+
+/// Why a message couldn't be queued for the server.
+#[derive(Debug, PartialEq)]
+pub enum SendError {
+    Serialize(SerializeError),
+    /// The network loop is behind on commands.
+    Busy,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Serialize(e) => e.fmt(f),
+            Self::Busy => write!(f, "too many commands waiting, try again"),
+        }
+    }
+}
+
+impl From<SerializeError> for SendError {
+    fn from(e: SerializeError) -> Self {
+        Self::Serialize(e)
     }
 }
 
+/// Longest host to assume while we don't know ours.
+const HOSTLEN: usize = 63;
+
+impl State {
+    /// Learn our own nick from NICK, our `user@host` from our JOIN echoes and from 396, and keep
+    /// track of the channels we're in. Returns the channels a NICK or QUIT touched.
+    fn observe(&mut self, msg: &ServMsg) -> Vec<String> {
+        let time = msg.server_time().unwrap_or_else(Utc::now);
+        let chans = self
+            .chans
+            .handle(&self.cur_nick, self.isupport.chanmodes(), msg, time);
+        let casemapping = self.isupport.casemapping();
+        match (&msg.prefix, &msg.command) {
+            (_, ServCmd::RplWelcome { nick, .. }) => self.cur_nick = nick.clone(),
+            (Some(Prefix::User { nick: old, .. }), ServCmd::Nick { nick })
+                if casemapping.eq(old, &self.cur_nick) =>
+            {
+                self.cur_nick = nick.clone();
+            }
+            (Some(Prefix::User { nick, user, host }), ServCmd::Join { .. })
+                if casemapping.eq(nick, &self.cur_nick) =>
+            {
+                self.user = user.clone();
+                self.host = Some(host.clone());
+            }
+            (_, ServCmd::DisplayedHost { host, .. }) => self.host = Some(host.clone()),
+            (_, ServCmd::RplISupport { tokens, .. }) => {
+                self.isupport.apply(tokens);
+                self.chans.set_casemapping(self.isupport.casemapping());
+            }
+            _ => {}
+        }
+        chans
+    }
+
+    fn privmsg_budget(&self, target: &str) -> usize {
+        let unknown_host = "x".repeat(HOSTLEN);
+        let host = self.host.as_deref().unwrap_or(&unknown_host);
+        privmsg_budget(&self.cur_nick, &self.user, host, target)
+    }
+}
+
+#[derive(Clone)]
 pub struct Client {
-    pub name: String,
-    pub cur_nick: String,
-    cmd_tx: Sender<String>,
+    pub id: String,
+    addr: String,
+    /// Configured name, which wins over the network's own.
+    name: Option<String>,
+    state: Rc<RefCell<State>>,
+    cmd_tx: Sender<NetCmd>,
 }
 
 impl Client {
@@ -37,30 +187,200 @@ impl Client {
         connect(serv_info)
     }
 
-    fn send(&self, msg: &str) {
-        self.cmd_tx
-            .try_send(msg.to_string())
-            .expect("failed to send message");
+    pub fn cur_nick(&self) -> String {
+        self.state.borrow().cur_nick.clone()
+    }
+
+    /// What to call the server: the configured name, else the network name once the server told
+    /// us, else the host.
+    pub fn name(&self) -> String {
+        let state = self.state.borrow();
+        self.name
+            .as_deref()
+            .or(state.isupport.network())
+            .unwrap_or(&self.addr)
+            .to_string()
+    }
+
+    /// Split a MODE message into changes the way this server means them.
+    pub fn mode(&self, target: &str, modes: &str, args: &[String]) -> Mode {
+        let state = self.state.borrow();
+        if state.isupport.is_channel(target) {
+            Mode::chan(target, modes, args, state.isupport.chanmodes())
+        } else {
+            Mode::user(target, modes)
+        }
+    }
+
+    /// Where a PRIVMSG from `nick` to `target` goes: the channel, or a query with `nick`.
+    pub fn msg_target(&self, target: &str, nick: &str) -> MsgTarget {
+        if self.state.borrow().isupport.is_channel(target) {
+            MsgTarget::Chan(target.to_string())
+        } else {
+            MsgTarget::User(nick.to_string())
+        }
+    }
+
+    /// How the server folds the case of nicks and channel names.
+    pub fn casemapping(&self) -> CaseMapping {
+        self.state.borrow().isupport.casemapping()
+    }
+
+    /// Longest nick the server takes, if it said.
+    pub fn nicklen(&self) -> Option<usize> {
+        self.state.borrow().isupport.nicklen()
+    }
+
+    pub fn topiclen(&self) -> Option<usize> {
+        self.state.borrow().isupport.topiclen()
+    }
+
+    /// What we know about a channel we're in.
+    pub fn channel(&self, chan: &str) -> Option<Channel> {
+        self.state.borrow().chans.get(chan).cloned()
+    }
+
+    /// Queue a message for the server, refusing it up front if it can't be serialized.
+    fn send(&self, msg: ClientMsg) -> Result<(), SendError> {
+        self.send_all(vec![msg])
+    }
+
+    /// Queue messages for the server, none of them if any can't be serialized.
+    fn send_all(&self, msgs: Vec<ClientMsg>) -> Result<(), SendError> {
+        for msg in &msgs {
+            msg.to_wire()?;
+        }
+        self.cmd(NetCmd::Send(msgs))
+    }
+
+    fn cmd(&self, cmd: NetCmd) -> Result<(), SendError> {
+        self.cmd_tx.try_send(cmd).map_err(|_| SendError::Busy)
+    }
+
+    pub fn quit(&self, msg: &str) -> Result<(), SendError> {
+        self.send(ClientMsg::Quit {
+            msg: msg.to_string(),
+        })
+    }
+
+    /// Join channels, with their keys if they have one, in as few JOINs as TARGMAX and the
+    /// line limit allow.
+    pub fn join(&self, chans: &[(String, Option<String>)]) -> Result<(), SendError> {
+        let max = {
+            let mut state = self.state.borrow_mut();
+            for (chan, key) in chans {
+                if let Some(key) = key {
+                    state
+                        .keys
+                        .insert(CaseMapping::Ascii.fold(chan), key.clone());
+                }
+            }
+            state.isupport.targmax("JOIN").flatten()
+        };
+        let msgs = join_batches(chans, max)
+            .into_iter()
+            .map(|(chan, key)| ClientMsg::Join { chan, key })
+            .collect();
+        self.send_all(msgs)
+    }
+
+    /// Join channels again after reconnecting, with the keys we joined them with.
+    pub fn rejoin(&self, chans: &[String]) -> Result<(), SendError> {
+        let chans = {
+            let state = self.state.borrow();
+            chans
+                .iter()
+                .map(|chan| {
+                    let key = state.keys.get(&CaseMapping::Ascii.fold(chan)).cloned();
+                    (chan.clone(), key)
+                })
+                .collect::<Vec<_>>()
+        };
+        self.join(&chans)
     }
 
-    pub fn quit(&self, msg: &str) {
-        self.send(&format!("QUIT :{}\r\n", msg));
+    pub fn topic(&self, chan: &str, topic: &str) -> Result<(), SendError> {
+        self.send(ClientMsg::Topic {
+            chan: chan.to_string(),
+            topic: topic.to_string(),
+        })
     }
 
-    pub fn join(&self, chan: &str) {
-        self.send(&format!("JOIN {}\r\n", chan));
+    /// Ask for a new nick. `cur_nick` changes once the server confirms it.
+    pub fn nick(&self, nick: &str) -> Result<(), SendError> {
+        self.send(ClientMsg::Nick {
+            nick: nick.to_string(),
+        })
     }
 
-    pub fn nick(&mut self, nick: &str) {
-        self.send(&format!("NICK {}\r\n", nick));
-        self.cur_nick = nick.to_string();
+    /// Send `msg` in as many PRIVMSGs as it takes to fit the line limit. Returns the pieces as
+    /// the others will see them.
+    pub fn privmsg(&self, target: &str, msg: &str) -> Result<Vec<String>, SendError> {
+        let budget = self.state.borrow().privmsg_budget(target);
+        let msgs = split_message(msg, budget)
+            .into_iter()
+            .map(|piece| ClientMsg::PrivMsg {
+                target: target.to_string(),
+                msg: piece.to_string(),
+            })
+            .collect::<Vec<_>>();
+        let pieces = msgs
+            .iter()
+            .filter_map(|msg| match msg {
+                ClientMsg::PrivMsg { msg, .. } => Some(msg.clone()),
+                _ => None,
+            })
+            .collect();
+        self.send_all(msgs)?;
+        Ok(pieces)
     }
 
-    pub fn privmsg(&self, target: &str, msg: &str) {
-        self.send(&format!("PRIVMSG {} :{}\r\n", target, msg));
+    pub fn reconnect(&self) -> Result<(), SendError> {
+        self.cmd(NetCmd::Reconnect)
     }
 }
 
+/// Channel and key lists for JOIN with at most `max` channels each, short enough to fit on a
+/// line. Keys go with the first channels of a JOIN, so channels with keys come first.
+fn join_batches(
+    chans: &[(String, Option<String>)],
+    max: Option<usize>,
+) -> Vec<(String, Option<String>)> {
+    let max = max.unwrap_or(usize::MAX).max(1);
+    let room = MAX_LINE_LEN - "JOIN \r\n".len();
+    let mut chans = chans.iter().collect::<Vec<_>>();
+    chans.sort_by_key(|(_, key)| key.is_none());
+    let mut batches: Vec<(String, Option<String>, usize)> = vec![];
+    for (chan, key) in chans {
+        let added = 1 + chan.len() + key.as_ref().map_or(0, |key| 1 + key.len());
+        match batches.last_mut() {
+            Some((batch, keys, n))
+                if *n < max
+                    && batch.len() + keys.as_ref().map_or(0, |keys| 1 + keys.len()) + added
+                        <= room =>
+            {
+                batch.push(',');
+                batch.push_str(chan);
+                if let Some(key) = key {
+                    match keys {
+                        Some(keys) => {
+                            keys.push(',');
+                            keys.push_str(key);
+                        }
+                        None => *keys = Some(key.clone()),
+                    }
+                }
+                *n += 1;
+            }
+            _ => batches.push((chan.clone(), key.clone(), 1)),
+        }
+    }
+    batches
+        .into_iter()
+        .map(|(batch, keys, _)| (batch, keys))
+        .collect()
+}
+
 fn connect(serv_info: ServInfo) -> (Client, Receiver<Event>, Receiver<String>) {
     // Channel for messages from the server.
     let (ev_tx, ev_rx) = tokio::sync::mpsc::channel(100);
@@ -69,14 +389,33 @@ fn connect(serv_info: ServInfo) -> (Client, Receiver<Event>, Receiver<String>) {
     // Channel to output all network activity as debug messages.
     let (dbg_tx, dbg_rx) = tokio::sync::mpsc::channel(100);
 
-    let name = serv_info.addr.clone();
-    let nick = serv_info.nick.clone();
-    tokio::task::spawn_local(network_loop(serv_info, ev_tx, dbg_tx, cmd_rx));
+    let id = serv_info.id.clone();
+    let addr = serv_info.addr.clone();
+    let name = serv_info.name.clone();
+    let state = Rc::new(RefCell::new(State {
+        cur_nick: serv_info.nick.clone(),
+        caps: Caps::default(),
+        // Servers put a `~` in front of user names they couldn't verify with ident.
+        user: format!("~{}", serv_info.user),
+        host: None,
+        chans: Channels::default(),
+        isupport: ISupport::default(),
+        keys: BTreeMap::new(),
+    }));
+    tokio::task::spawn_local(network_loop(
+        serv_info,
+        state.clone(),
+        ev_tx,
+        dbg_tx,
+        cmd_rx,
+    ));
 
     (
         Client {
+            id,
+            addr,
             name,
-            cur_nick: nick,
+            state,
             cmd_tx,
         },
         ev_rx,
@@ -89,38 +428,68 @@ pub async fn handle_network_events(
     mut ev_rx: Receiver<Event>,
     mut dbg_rx: Receiver<String>,
     tui: UI,
-    serv_name: String,
+    client: Client,
 ) {
+    let serv_id = client.id.clone();
     loop {
         tokio::select! {
             Some(ev) = ev_rx.recv() => {
                 match ev {
-                    Event::Disconnected => {
-                        tui.dbg(&format!("{}: TcpStream disconnected", &serv_name));
+                    Event::Connected { tls } => {
+                        tui.add_serv_msg(&serv_id, &format!("Connected to {} ({tls})", client.addr));
+                        tui.draw();
+                    }
+                    Event::ConnectFailed { err } => {
+                        tui.add_serv_msg(&serv_id, &format!("Could not connect to {}: {err}", client.addr));
+                        tui.draw();
+                    }
+                    Event::Disconnected { reason } => {
+                        tui.add_serv_msg(&serv_id, &format!("Disconnected: {reason}"));
+                        tui.set_lag(&serv_id, None);
+                        tui.draw();
+                    }
+                    Event::Reconnecting { attempt, delay } => {
+                        tui.add_serv_msg(&serv_id, &format!(
+                            "Reconnecting in {:.1}s (attempt {attempt}), /reconnect to retry now",
+                            delay.as_secs_f64()
+                        ));
+                        tui.draw();
+                    }
+                    Event::Queued { len } => {
+                        tui.set_queued(&serv_id, len);
                         tui.draw();
-                        break;
                     }
-                    Event::Msg { msg } => {
+                    Event::Lag { lag } => {
+                        tui.set_lag(&serv_id, Some(lag));
+                        tui.draw();
+                    }
+                    Event::Msg { msg, chans } => {
+                        let time = msg.server_time().unwrap_or_else(Utc::now);
                         let ServMsg {
                             prefix,
                             command,
+                            ..
                         } = msg;
                         match command {
                             ServCmd::PrivMsg { target, msg } => {
                                 match &prefix {
                                     Some(Prefix::User { nick, .. }) => {
-                                        // TODO display @/+/etc
-                                        tui.add_msg(&serv_name, target, &format!("<{nick}> {msg}"));
+                                        let prefix = client.channel(&target)
+                                            .and_then(|chan| chan.prefix(nick))
+                                            .map(String::from)
+                                            .unwrap_or_default();
+                                        let target = client.msg_target(&target, nick);
+                                        tui.add_msg_at(time, &serv_id, target, &format!("<{prefix}{nick}> {msg}"));
                                     }
                                     Some(Prefix::Server(serv)) => {
-                                        tui.add_serv_msg(&serv_name, &format!("[{serv}] {msg}"));
+                                        tui.add_serv_msg_at(time, &serv_id, &format!("[{serv}] {msg}"));
                                     }
-                                    _ => tui.dbg(&format!("[{}] PRIVMSG with no prefix {msg:?}", serv_name)),
+                                    _ => tui.dbg(&format!("[{}] PRIVMSG with no prefix {msg:?}", serv_id)),
                                 }
                             }
                             ServCmd::Join { chan } => {
                                 if let Some(Prefix::User { nick, user, host }) = &prefix {
-                                    tui.add_msg(&serv_name, MsgTarget::Chan(chan.clone()),
+                                    tui.add_msg_at(time, &serv_id, MsgTarget::Chan(chan.clone()),
                                         &format!("{nick} ({user}@{host}) joined {chan}"));
                                 }
                             }
@@ -131,49 +500,163 @@ pub async fn handle_network_events(
                                     } else {
                                         format!("{nick} ({user}@{host}) left {chan} ({msg})")
                                     };
-                                    tui.add_msg(&serv_name, MsgTarget::Chan(chan.clone()), &msg);
+                                    tui.add_msg_at(time, &serv_id, MsgTarget::Chan(chan.clone()), &msg);
                                 }
                             }
                             ServCmd::Nick { nick } => {
-                                // Same message if self or other changes nick.
-                                // No channel indication--have to keep track of nicks in each channel.
-                                // Print message in relevant channels.
-                                // Should solve for self as well, since self is in all channels.
                                 if let Some(Prefix::User { nick: old_nick, .. }) = &prefix {
-                                    tui.add_msg(&serv_name, MsgTarget::Serv(serv_name.clone()),
-                                        &format!("{old_nick} is now known as {nick}"));
+                                    let msg = format!("{old_nick} is now known as {nick}");
+                                    // Ours is news everywhere, even where nobody else is around.
+                                    if nick == client.cur_nick() {
+                                        tui.add_serv_msg_at(time, &serv_id, &msg);
+                                    }
+                                    for chan in chans {
+                                        tui.add_msg_at(time, &serv_id, MsgTarget::Chan(chan), &msg);
+                                    }
+                                    if tui.rename_query(&serv_id, old_nick, &nick) {
+                                        tui.add_msg_at(time, &serv_id, MsgTarget::User(nick), &msg);
+                                    }
+                                }
+                            }
+                            ServCmd::Quit { msg } => {
+                                if let Some(Prefix::User { nick, user, host }) = &prefix {
+                                    let msg = format!("{nick} ({user}@{host}) has quit ({msg})");
+                                    for chan in chans {
+                                        tui.add_msg_at(time, &serv_id, MsgTarget::Chan(chan), &msg);
+                                    }
+                                    if tui.has_query(&serv_id, nick) {
+                                        tui.add_msg_at(time, &serv_id, MsgTarget::User(nick.clone()), &msg);
+                                    }
                                 }
                             }
-                            ServCmd::Notice { msg } => tui.add_serv_msg(&serv_name, &msg),
+                            ServCmd::Kick { chan, nick, msg } => {
+                                let by = prefix.as_ref().map(Prefix::name).unwrap_or_default();
+                                tui.add_msg_at(time, &serv_id, MsgTarget::Chan(chan.clone()),
+                                    &format!("{nick} was kicked from {chan} by {by} ({msg})"));
+                            }
+                            ServCmd::Mode { target, modes, args } => {
+                                let by = prefix.as_ref().map(Prefix::name).unwrap_or_default();
+                                match client.mode(&target, &modes, &args) {
+                                    Mode::Chan { chan, changes } => {
+                                        for change in changes {
+                                            tui.add_msg_at(time, &serv_id, MsgTarget::Chan(chan.clone()),
+                                                &format!("{by} sets mode {change}"));
+                                        }
+                                    }
+                                    Mode::User { nick, changes } => {
+                                        let changes = changes.iter().map(|change| change.to_string()).collect::<Vec<_>>();
+                                        tui.add_serv_msg_at(time, &serv_id,
+                                            &format!("{by} sets mode {} on {nick}", changes.join(" ")));
+                                    }
+                                }
+                            }
+                            ServCmd::Topic { chan, topic } => {
+                                let by = prefix.as_ref().map(Prefix::name).unwrap_or_default();
+                                tui.add_msg_at(time, &serv_id, MsgTarget::Chan(chan),
+                                    &format!("{by} changed the topic to: {topic}"));
+                            }
+                            ServCmd::RplChannelModeIs { chan, .. } => {
+                                if let Some(chan) = client.channel(&chan) {
+                                    tui.add_msg_at(time, &serv_id, MsgTarget::Chan(chan.name.clone()),
+                                        &format!("Modes: {}", chan.modes()));
+                                }
+                            }
+                            ServCmd::RplTopic { chan, topic } => {
+                                tui.add_msg_at(time, &serv_id, MsgTarget::Chan(chan), &format!("Topic: {topic}"));
+                            }
+                            ServCmd::RplTopicWhoTime { chan, setter, .. } => {
+                                let set_at = client.channel(&chan)
+                                    .and_then(|chan| chan.topic?.set_at)
+                                    .map(|set_at| tui.format_time(set_at))
+                                    .unwrap_or_default();
+                                tui.add_msg_at(time, &serv_id, MsgTarget::Chan(chan),
+                                    &format!("Topic set by {setter} {set_at}"));
+                            }
+                            ServCmd::Notice { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
                             ServCmd::Error { msg } => {
-                                tui.add_serv_msg(&serv_name, &msg);
-                                // Do not break here--wait for the Event::Disconnected message to
-                                // break out of the loop.
+                                tui.add_serv_msg_at(time, &serv_id, &msg);
+                                // Do not break here--the network loop reports the disconnection
+                                // and decides whether to reconnect.
+                            }
+                            ServCmd::Cap { subcmd, caps, .. } => {
+                                let caps = caps.join(" ");
+                                let msg = match subcmd.as_str() {
+                                    "LS" => format!("Capabilities offered: {caps}"),
+                                    "ACK" => {
+                                        let state = client.state.borrow();
+                                        let enabled = state.caps.enabled().collect::<Vec<_>>();
+                                        format!("Capabilities enabled: {}", enabled.join(" "))
+                                    }
+                                    "NAK" => format!("Capabilities rejected: {caps}"),
+                                    "NEW" => format!("Capabilities added: {caps}"),
+                                    "DEL" => format!("Capabilities removed: {caps}"),
+                                    _ => format!("CAP {subcmd} {caps}"),
+                                };
+                                tui.add_serv_msg_at(time, &serv_id, &msg);
                             }
-                            ServCmd::RplWelcome { msg } => tui.add_serv_msg(&serv_name, &msg),
-                            ServCmd::RplYourHost { msg } => tui.add_serv_msg(&serv_name, &msg),
-                            ServCmd::RplCreated { msg } => tui.add_serv_msg(&serv_name, &msg),
+                            ServCmd::RplWelcome { msg, .. } => {
+                                tui.add_serv_msg_at(time, &serv_id, &msg);
+                                // Rejoin the channels that survived a reconnect.
+                                let chans = tui.chans(&serv_id);
+                                if let Err(e) = client.rejoin(&chans) {
+                                    tui.dbg(&format!("[{serv_id}] Cannot rejoin {chans:?}: {e}"));
+                                }
+                            }
+                            ServCmd::RplYourHost { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::RplCreated { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
                             ServCmd::RplMyInfo { version, umodes, cmodes, cmodes_param } => {
-                                tui.add_serv_msg(&serv_name, &format!("{version} {umodes} {cmodes} {cmodes_param}"));
+                                tui.add_serv_msg_at(time, &serv_id, &format!("{version} {umodes} {cmodes} {cmodes_param}"));
+                            }
+                            ServCmd::RplISupport { tokens, msg } => {
+                                tui.set_casemapping(&serv_id, client.casemapping());
+                                tui.set_serv_name(&serv_id, &client.name());
+                                tui.add_serv_msg_at(time, &serv_id, &format!("{} {msg}", tokens.join(" ")));
                             }
-                            ServCmd::RplISupport { msg } => tui.add_serv_msg(&serv_name, &msg),
-                            ServCmd::RplLuserClient { msg } => tui.add_serv_msg(&serv_name, &msg),
-                            ServCmd::RplLuserOp { msg } => tui.add_serv_msg(&serv_name, &msg),
-                            ServCmd::RplLuserUnknown { msg } => tui.add_serv_msg(&serv_name, &msg),
-                            ServCmd::RplLuserChannels { msg } => tui.add_serv_msg(&serv_name, &msg),
-                            ServCmd::RplLuserMe { msg } => tui.add_serv_msg(&serv_name, &msg),
-                            ServCmd::RplLocalUsers { msg } => tui.add_serv_msg(&serv_name, &msg),
-                            ServCmd::RplGlobalUsers { msg } => tui.add_serv_msg(&serv_name, &msg),
-                            ServCmd::NameReply { sym, chan, nicks } => {
-                                let nicks = nicks.join(" ");
-                                tui.add_serv_msg(&serv_name, &format!("{sym} {chan} {nicks}"));
-                            },
-                            ServCmd::EndOfNames { msg } => tui.add_serv_msg(&serv_name, &msg),
-                            ServCmd::MOTDStart { msg } => tui.add_serv_msg(&serv_name, &msg),
-                            ServCmd::Motd { msg } => tui.add_serv_msg(&serv_name, &msg),
-                            ServCmd::MOTDEnd { msg } => tui.add_serv_msg(&serv_name, &msg),
-                            ServCmd::DisplayedHost { msg } => tui.add_serv_msg(&serv_name, &msg),
-                            _ => tui.dbg(&format!("[{}] unhandled command {command:?}", serv_name)),
+                            ServCmd::RplLuserClient { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::RplLuserOp { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::RplLuserUnknown { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::RplLuserChannels { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::RplLuserMe { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::RplLocalUsers { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::RplGlobalUsers { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            // Collected into the channel state until 366.
+                            ServCmd::NameReply { .. } => {}
+                            ServCmd::EndOfNames { chan, msg } => {
+                                match client.channel(&chan) {
+                                    Some(chan) => tui.show_names(time, &serv_id, &chan),
+                                    None => tui.add_serv_msg_at(time, &serv_id, &format!("{chan} {msg}")),
+                                }
+                            }
+                            ServCmd::MOTDStart { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::Motd { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::MOTDEnd { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::DisplayedHost { host, msg } => {
+                                tui.add_serv_msg_at(time, &serv_id, &format!("{host} {msg}"));
+                            }
+                            ServCmd::ErrNoNicknameGiven { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::ErrErroneusNickname { nick, msg }
+                            | ServCmd::ErrNicknameInUse { nick, msg }
+                            | ServCmd::ErrNickCollision { nick, msg }
+                            | ServCmd::ErrUnavailResource { nick, msg } => {
+                                tui.add_serv_msg_at(time, &serv_id, &format!("{nick}: {msg}"));
+                            }
+                            // Only asked for to regain our nick, which the network loop takes care of.
+                            ServCmd::RplMonOnline { .. } | ServCmd::RplMonOffline { .. } => {}
+                            // The exchange itself is handled by the network loop.
+                            ServCmd::Authenticate { .. } => {}
+                            ServCmd::RplLoggedIn { msg, .. } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::RplLoggedOut { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::ErrNickLocked { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::RplSaslSuccess { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::ErrSaslFail { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::ErrSaslTooLong { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::ErrSaslAborted { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::ErrSaslAlready { msg } => tui.add_serv_msg_at(time, &serv_id, &msg),
+                            ServCmd::RplSaslMechs { mechs } => {
+                                let mechs = mechs.join(", ");
+                                tui.add_serv_msg_at(time, &serv_id, &format!("Available SASL mechanisms: {mechs}"));
+                            }
+                            _ => tui.dbg(&format!("[{}] unhandled command {command:?}", serv_id)),
                         }
                         tui.draw();
                     }
@@ -184,75 +667,499 @@ pub async fn handle_network_events(
                 tui.dbg(&msg);
                 tui.draw();
             }
+
+            else => break,
         }
     }
 }
 
-/// Low level communication with the server.
+/// How a connection to the server came to an end.
+enum SessionEnd {
+    /// We sent QUIT; stay offline until asked to reconnect.
+    Quit,
+    /// The connection dropped or could not be established; retry after a delay.
+    Lost,
+    /// The user asked for a new connection right away.
+    Reconnect,
+    /// The client is gone.
+    Closed,
+}
+
+/// What the network loop holds on to across connections.
+struct Net {
+    serv_info: ServInfo,
+    state: Rc<RefCell<State>>,
+    ev_tx: Sender<Event>,
+    dbg_tx: Sender<String>,
+    cmd_rx: Receiver<NetCmd>,
+    /// Failed attempts since the last successful registration.
+    attempt: u32,
+    /// The nick to register with: the configured one, or the last one asked for with /nick.
+    wanted_nick: String,
+}
+
+impl Net {
+    async fn event(&self, ev: Event) {
+        self.ev_tx.send(ev).await.expect("failed to send message");
+    }
+
+    async fn dbg(&self, msg: String) {
+        self.dbg_tx
+            .send(msg)
+            .await
+            .expect("failed to send debug message");
+    }
+}
+
+/// Low level communication with the server. Keeps reconnecting with exponential backoff until
+/// the user quits.
 async fn network_loop(
     serv_info: ServInfo,
+    state: Rc<RefCell<State>>,
     ev_tx: Sender<Event>,
     dbg_tx: Sender<String>,
-    mut cmd_rx: Receiver<String>,
+    cmd_rx: Receiver<NetCmd>,
 ) {
-    let host = format!("{}:{}", serv_info.addr, serv_info.port);
-    let stream = TcpStream::connect(host)
-        .await
-        .expect("failed to connect to server");
+    let mut net = Net {
+        wanted_nick: serv_info.nick.clone(),
+        serv_info,
+        state,
+        ev_tx,
+        dbg_tx,
+        cmd_rx,
+        attempt: 0,
+    };
+    loop {
+        let end = match open_stream(&net.serv_info).await {
+            Ok((stream, tls)) => {
+                net.event(Event::Connected { tls }).await;
+                session(&mut net, stream).await
+            }
+            Err(e) => {
+                let err = e.to_string();
+                net.event(Event::ConnectFailed { err }).await;
+                SessionEnd::Lost
+            }
+        };
+
+        match end {
+            SessionEnd::Closed => break,
+            SessionEnd::Reconnect => net.attempt = 0,
+            SessionEnd::Quit => {
+                if !wait_for_reconnect(&mut net).await {
+                    break;
+                }
+                net.attempt = 0;
+            }
+            SessionEnd::Lost => {
+                let delay = net.serv_info.backoff.delay(net.attempt, rand::random());
+                net.attempt += 1;
+                let attempt = net.attempt;
+                net.event(Event::Reconnecting { attempt, delay }).await;
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    reconnect = wait_for_reconnect(&mut net) => {
+                        if !reconnect {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Register and then shuttle lines between the server and the app until the connection ends.
+async fn session(net: &mut Net, stream: Box<dyn Stream>) -> SessionEnd {
     let (reader, mut writer) = tokio::io::split(stream);
     let mut reader = BufReader::new(reader).lines();
+    let mut quitting = false;
+    let mut queue = SendQueue::new(net.serv_info.flood.clone(), Instant::now());
+    let mut queued = 0;
+    let mut pinger = Pinger::new(net.serv_info.keepalive.clone(), Instant::now());
+    let mut nicks = Nicks::new(&net.wanted_nick, &net.serv_info.alt_nicks);
+    let mut registered = false;
 
-    send(&mut writer, &format!("NICK {}\r\n", serv_info.nick))
-        .await
-        .expect("network_loop: failed to send NICK");
-    send(
-        &mut writer,
-        &format!("USER {} 0 * :{}\r\n", serv_info.user, serv_info.real),
-    )
-    .await
-    .expect("network_loop: failed to send USER");
+    // Capabilities are negotiated from scratch on every connection. CAP LS goes first so that
+    // the server holds registration until CAP END.
+    let mut registration = {
+        let mut state = net.state.borrow_mut();
+        let sasl = net.serv_info.sasl.clone().map(Authenticator::new);
+        state.caps = Caps::with_sasl(sasl);
+        state.chans = Channels::default();
+        state.isupport = ISupport::default();
+        state.caps.start()
+    };
+    registration.push(ClientMsg::Nick {
+        nick: nicks.wanted().to_string(),
+    });
+    registration.push(ClientMsg::User {
+        user: net.serv_info.user.clone(),
+        real: net.serv_info.real.clone(),
+    });
+    for msg in registration {
+        queue.push(msg);
+    }
+
+    let reason = loop {
+        // Write out as much as the flood limit allows.
+        if let Err(e) = flush(&mut writer, &mut queue).await {
+            break format!("failed to send command: {e}");
+        }
+        if queue.len() != queued {
+            queued = queue.len();
+            net.event(Event::Queued { len: queued }).await;
+        }
+        let next_send = queue.next_ready(Instant::now());
+        let next_ping = pinger.deadline();
+        let next_regain = nicks.deadline();
 
-    loop {
         tokio::select! {
             line = reader.next_line() => {
                 match line {
-                    Ok(Some(line)) if line.starts_with("PING") => {
-                        let pong = format!("PONG {}\r\n", &line[5..]);
-                        send(&mut writer, &pong).await.expect("failed to send PONG");
-                    }
                     Ok(Some(line)) => {
-                        dbg_tx.send(line.clone()).await.expect("failed to send debug message");
+                        let msg = match parse_msg(&line) {
+                            Ok(msg) => msg,
+                            Err(e) => {
+                                net.dbg(format!("Could not parse {line:?}: {e}")).await;
+                                continue;
+                            }
+                        };
+                        if let ServCmd::Ping { token } = &msg.command {
+                            queue.push(ClientMsg::Pong { token: token.clone() });
+                            continue;
+                        }
+                        if let ServCmd::Pong { token } = &msg.command {
+                            if let Some(lag) = pinger.pong(token, Instant::now()) {
+                                net.event(Event::Lag { lag }).await;
+                                continue;
+                            }
+                        }
+                        net.dbg(line.clone()).await;
+                        if let Some(nick) = nick_error(&msg.command) {
+                            if !registered {
+                                match nicks.next() {
+                                    Some(nick) => queue.push(ClientMsg::Nick { nick }),
+                                    None => net.dbg("Out of nicks to try, use /nick".to_string()).await,
+                                }
+                            } else if nicks.is_regaining(nick) {
+                                continue;
+                            }
+                        }
+                        let chans = net.state.borrow_mut().observe(&msg);
+                        match &msg.command {
+                            ServCmd::RplWelcome { nick, .. } => {
+                                net.attempt = 0;
+                                registered = true;
+                                nicks.registered(nick, Instant::now());
+                            }
+                            ServCmd::Nick { nick } if *nick == net.state.borrow().cur_nick => {
+                                if let Some(msg) = nicks.changed(nick) {
+                                    queue.push(msg);
+                                }
+                            }
+                            ServCmd::RplMonOffline { targets } => {
+                                if let Some(msg) = nicks.offline(targets) {
+                                    queue.push(msg);
+                                }
+                            }
+                            _ => {}
+                        }
+                        // 005 comes after 001, so this usually waits for it.
+                        nicks.set_casemapping(net.state.borrow().isupport.casemapping());
+                        if net.state.borrow().isupport.monitor() {
+                            if let Some(msg) = nicks.monitor() {
+                                queue.push(msg);
+                            }
+                        }
+                        let replies = net.state.borrow_mut().caps.handle(&msg.command);
+                        // A failed mandatory SASL login quits rather than retrying forever.
+                        quitting |= replies.iter().any(|msg| matches!(msg, ClientMsg::Quit { .. }));
+                        net.event(Event::Msg { msg, chans }).await;
+                        for reply in replies {
+                            queue.push(reply);
+                        }
+                    }
+                    Ok(None) => break "connection closed by server".to_string(),
+                    Err(e) => break format!("error reading from server: {e}"),
+                }
+            }
 
-                        let msg = parse_msg(&line);
-                        ev_tx
-                            .send(Event::Msg { msg })
-                            .await
-                            .expect("failed to send message");
+            cmd = net.cmd_rx.recv() => {
+                match cmd {
+                    Some(NetCmd::Send(cmds)) => {
+                        for cmd in cmds {
+                            quitting |= matches!(cmd, ClientMsg::Quit { .. });
+                            if let ClientMsg::Nick { nick } = &cmd {
+                                net.wanted_nick = nick.clone();
+                                if let Some(msg) = nicks.want(nick) {
+                                    queue.push(msg);
+                                }
+                            }
+                            queue.push(cmd);
+                        }
                     }
-                    Ok(None) => {
-                        ev_tx.send(Event::Disconnected).await.expect("failed to send message");
-                        break;
+                    Some(NetCmd::Reconnect) => {
+                        // Best effort; the connection is dropped either way.
+                        let quit = ClientMsg::Quit { msg: "Reconnecting".to_string() };
+                        let _ = send(&mut writer, &quit).await;
+                        let reason = "reconnecting".to_string();
+                        net.event(Event::Disconnected { reason }).await;
+                        drop_queue(net, &queue).await;
+                        return SessionEnd::Reconnect;
                     }
-                    Err(e) => {
-                        eprintln!("error reading from server: {}", e);
-                        break;
+                    None => return SessionEnd::Closed,
+                }
+            }
+
+            _ = sleep_until(next_send), if next_send.is_some() => {}
+
+            _ = sleep_until(next_regain), if next_regain.is_some() => {
+                if let Some(msg) = nicks.tick(Instant::now()) {
+                    queue.push(msg);
+                }
+            }
+
+            _ = tokio::time::sleep_until(next_ping.into()) => {
+                match pinger.tick(Instant::now()) {
+                    Tick::Wait => {}
+                    // Straight out rather than through the queue, or flood control would count
+                    // towards the lag.
+                    Tick::Ping(token) => {
+                        if let Err(e) = send(&mut writer, &ClientMsg::Ping { token }).await {
+                            break format!("failed to send command: {e}");
+                        }
+                    }
+                    Tick::TimedOut => {
+                        let timeout = net.serv_info.keepalive.timeout.as_secs();
+                        break format!("ping timeout ({timeout}s)");
                     }
                 }
             }
+        }
+    };
+
+    net.event(Event::Disconnected { reason }).await;
+    drop_queue(net, &queue).await;
+    if quitting {
+        SessionEnd::Quit
+    } else {
+        SessionEnd::Lost
+    }
+}
+
+/// The nick a server turned down, if that's what `cmd` is about.
+fn nick_error(cmd: &ServCmd) -> Option<&str> {
+    match cmd {
+        ServCmd::ErrNoNicknameGiven { .. } => Some(""),
+        ServCmd::ErrErroneusNickname { nick, .. }
+        | ServCmd::ErrNicknameInUse { nick, .. }
+        | ServCmd::ErrNickCollision { nick, .. }
+        | ServCmd::ErrUnavailResource { nick, .. } => Some(nick),
+        _ => None,
+    }
+}
+
+/// Send what the flood limit lets through right now.
+async fn flush<W>(stream: &mut W, queue: &mut SendQueue) -> io::Result<()>
+where
+    W: AsyncWriteExt + Unpin,
+{
+    while let Some(msg) = queue.pop(Instant::now()) {
+        send(stream, &msg).await?;
+    }
+    Ok(())
+}
+
+/// Whatever was still queued was meant for the connection that just ended.
+async fn drop_queue(net: &Net, queue: &SendQueue) {
+    if queue.len() > 0 {
+        net.dbg(format!("Dropping {} queued messages", queue.len()))
+            .await;
+        net.event(Event::Queued { len: 0 }).await;
+    }
+}
 
-            cmd = cmd_rx.recv() => {
-                if let Some(cmd) = cmd {
-                    send(&mut writer, &cmd).await.expect("failed to send command: {cmd}");
+async fn sleep_until(deadline: Option<Instant>) {
+    if let Some(deadline) = deadline {
+        tokio::time::sleep_until(deadline.into()).await;
+    }
+}
+
+/// Wait while disconnected until the user asks to reconnect. Returns false if the client is gone.
+async fn wait_for_reconnect(net: &mut Net) -> bool {
+    loop {
+        match net.cmd_rx.recv().await {
+            Some(NetCmd::Reconnect) => return true,
+            Some(NetCmd::Send(cmds)) => {
+                for cmd in cmds {
+                    net.dbg(format!("Not connected, dropping {cmd:?}")).await;
                 }
             }
+            None => return false,
         }
     }
 }
 
-async fn send<W>(stream: &mut W, msg: &str) -> io::Result<()>
+trait Stream: AsyncRead + AsyncWrite + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Stream for T {}
+
+/// Connect to the server, wrapping the socket in TLS if asked to.
+async fn open_stream(serv_info: &ServInfo) -> io::Result<(Box<dyn Stream>, TlsState)> {
+    let stream = TcpStream::connect((serv_info.addr.as_str(), serv_info.port)).await?;
+    if serv_info.tls {
+        let (stream, state) = tls::handshake(
+            stream,
+            &serv_info.addr,
+            serv_info.tls_verify,
+            serv_info.tls_cert.as_deref(),
+        )
+        .await?;
+        Ok((Box::new(stream), state))
+    } else {
+        Ok((Box::new(stream), TlsState::Plain))
+    }
+}
+
+async fn send<W>(stream: &mut W, msg: &ClientMsg) -> io::Result<()>
 where
     W: AsyncWriteExt + Unpin,
 {
-    stream.write_all(msg.as_bytes()).await?;
+    let line = msg
+        .to_wire()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    stream.write_all(format!("{line}\r\n").as_bytes()).await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sasl::Mechanism;
+
+    fn client() -> (Client, Receiver<NetCmd>) {
+        let (cmd_tx, cmd_rx) = tokio::sync::mpsc::channel(1);
+        let state = State {
+            cur_nick: "me".to_string(),
+            caps: Caps::default(),
+            user: "~me".to_string(),
+            host: None,
+            chans: Channels::default(),
+            isupport: ISupport::default(),
+            keys: BTreeMap::new(),
+        };
+        let client = Client {
+            id: "irc.example.org/1".to_string(),
+            addr: "irc.example.org".to_string(),
+            name: None,
+            state: Rc::new(RefCell::new(state)),
+            cmd_tx,
+        };
+        (client, cmd_rx)
+    }
+
+    #[test]
+    fn test_join_batches() {
+        let chans = |chans: &[(&str, Option<&str>)]| {
+            chans
+                .iter()
+                .map(|(chan, key)| (chan.to_string(), key.map(str::to_string)))
+                .collect::<Vec<_>>()
+        };
+        let plain = chans(&[("#a", None), ("#b", None), ("#c", None)]);
+        assert_eq!(join_batches(&plain, None), chans(&[("#a,#b,#c", None)]));
+        assert_eq!(
+            join_batches(&plain, Some(2)),
+            chans(&[("#a,#b", None), ("#c", None)])
+        );
+        assert_eq!(join_batches(&plain, Some(0)), plain);
+        assert!(join_batches(&[], None).is_empty());
+
+        // Keyed channels move to the front so their keys line up.
+        let keyed = chans(&[
+            ("#a", None),
+            ("#b", Some("kb")),
+            ("#c", None),
+            ("#d", Some("kd")),
+        ]);
+        assert_eq!(
+            join_batches(&keyed, None),
+            chans(&[("#b,#d,#a,#c", Some("kb,kd"))])
+        );
+        assert_eq!(
+            join_batches(&keyed, Some(3)),
+            chans(&[("#b,#d,#a", Some("kb,kd")), ("#c", None)])
+        );
+
+        let long = (0..100)
+            .map(|n| (format!("#channel{n:02}"), Some(format!("key{n:02}"))))
+            .collect::<Vec<_>>();
+        let batches = join_batches(&long, None);
+        assert_eq!(batches.len(), 4);
+        let joined = batches.iter().map(|(batch, _)| batch.as_str());
+        let names = long.iter().map(|(chan, _)| chan.as_str());
+        assert_eq!(
+            joined.collect::<Vec<_>>().join(","),
+            names.collect::<Vec<_>>().join(",")
+        );
+        for (batch, keys) in &batches {
+            let msg = ClientMsg::Join {
+                chan: batch.clone(),
+                key: keys.clone(),
+            };
+            assert!(msg.to_wire().unwrap().len() + "\r\n".len() <= MAX_LINE_LEN);
+            assert_eq!(
+                batch.split(',').count(),
+                keys.as_ref().unwrap().split(',').count()
+            );
+        }
+    }
+
+    #[test]
+    fn test_privmsg_many_pieces() {
+        let (client, mut cmd_rx) = client();
+        let msg = "word ".repeat(20_000);
+        let pieces = client.privmsg("#chan", &msg).unwrap();
+        assert!(pieces.len() > 100);
+        match cmd_rx.try_recv() {
+            Ok(NetCmd::Send(msgs)) => assert_eq!(msgs.len(), pieces.len()),
+            cmd => panic!("unexpected {cmd:?}"),
+        }
+        // Refused rather than a panic while the network loop is behind
+        client.privmsg("#chan", "one").unwrap();
+        assert_eq!(client.privmsg("#chan", "two"), Err(SendError::Busy));
+        assert_eq!(client.reconnect(), Err(SendError::Busy));
+    }
+
+    #[test]
+    fn test_serv_info_debug_hides_password() {
+        let serv_info = ServInfo {
+            id: "irc.example.org/1".to_string(),
+            name: None,
+            addr: "irc.example.org".to_string(),
+            port: 6697,
+            tls: true,
+            tls_verify: true,
+            tls_cert: None,
+            sasl: Some(SaslConfig {
+                mech: Mechanism::ScramSha256 {
+                    user: "jilles".to_string(),
+                    pass: "sesame".to_string(),
+                },
+                required: false,
+            }),
+            nick: "jilles".to_string(),
+            alt_nicks: vec![],
+            user: "guest".to_string(),
+            real: "Jilles".to_string(),
+            backoff: Backoff::default(),
+            flood: Flood::default(),
+            keepalive: Keepalive::default(),
+        };
+        let debug = format!("{serv_info:?}");
+        assert!(debug.contains("irc.example.org"));
+        assert!(debug.contains("jilles"));
+        assert!(!debug.contains("sesame"));
+    }
+}
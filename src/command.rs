@@ -2,26 +2,134 @@
 
 #[derive(Debug, PartialEq)]
 pub enum Cmd {
-    Connect(String),
-    Join(String),
+    Connect(ServAddr),
+    /// Channels with their keys
+    Join(Vec<(String, Option<String>)>),
     Quit(String),
+    Reconnect,
     Nick(String),
+    /// List the members of the current channel.
+    Names,
+    /// Show the topic of the current channel, or set it.
+    Topic(Option<String>),
     Msg(String),
-    Unsupported { cmd: String, rest: String },
+    Unsupported {
+        cmd: String,
+        rest: String,
+    },
+}
+
+/// Where and how to connect, as given to `/connect`.
+#[derive(Debug, PartialEq)]
+pub struct ServAddr {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    /// Verify the server certificate against the system roots.
+    pub verify: bool,
+    /// What to call the server instead of its network name.
+    pub name: Option<String>,
+}
+
+const PLAIN_PORT: u16 = 6667;
+const TLS_PORT: u16 = 6697;
+
+/// `[-tls] [-insecure] [-name <name>] <host>[:<port>]` or `irc[s]://<host>[:<port>]`.
+/// `-insecure` implies TLS. IPv6 addresses take a port in brackets: `[::1]:6697`.
+fn parse_serv_addr(rest: &str) -> Result<ServAddr, &'static str> {
+    let mut tls = false;
+    let mut verify = true;
+    let mut name = None;
+    let mut addr = None;
+    let mut words = rest.split_whitespace();
+    while let Some(word) = words.next() {
+        match word {
+            "-tls" => tls = true,
+            "-insecure" => {
+                tls = true;
+                verify = false;
+            }
+            "-name" => name = Some(words.next().ok_or("No name given to -name")?.to_string()),
+            _ if word.starts_with('-') => return Err("Unknown /connect flag"),
+            _ if addr.is_some() => return Err("Too many server addresses"),
+            _ => addr = Some(word),
+        }
+    }
+    let mut addr = addr.ok_or("No server address provided")?;
+
+    if let Some(rest) = addr.strip_prefix("ircs://") {
+        tls = true;
+        addr = rest;
+    } else if let Some(rest) = addr.strip_prefix("irc://") {
+        addr = rest;
+    }
+    let addr = addr.trim_end_matches('/');
+
+    let (host, port) = match addr.strip_prefix('[') {
+        Some(rest) => {
+            let (host, rest) = rest.split_once(']').ok_or("Missing ] after IPv6 address")?;
+            let port = match rest {
+                "" => None,
+                _ => Some(rest.strip_prefix(':').ok_or("Invalid port")?),
+            };
+            (host, port)
+        }
+        // A bare IPv6 address, which can't have a port
+        None if addr.matches(':').count() > 1 => (addr, None),
+        None => match addr.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (addr, None),
+        },
+    };
+    let port = port
+        .map(|port| port.parse().map_err(|_| "Invalid port"))
+        .transpose()?;
+    if host.is_empty() {
+        return Err("No server address provided");
+    }
+
+    Ok(ServAddr {
+        host: host.to_string(),
+        port: port.unwrap_or(if tls { TLS_PORT } else { PLAIN_PORT }),
+        tls,
+        verify,
+        name,
+    })
+}
+
+/// `<chan>[,<chan>...] [<key>[,<key>...]]`, keys going with the channels in order.
+fn parse_join(rest: &str) -> Result<Vec<(String, Option<String>)>, &'static str> {
+    let mut words = rest.split_whitespace();
+    let chans = words.next().ok_or("No channel name provided")?;
+    let mut keys = words.next().unwrap_or("").split(',');
+    if words.next().is_some() {
+        return Err("Too many arguments to /join");
+    }
+    let chans = chans
+        .split(',')
+        .filter(|chan| !chan.is_empty())
+        .map(|chan| {
+            let key = keys.next().filter(|key| !key.is_empty());
+            (chan.to_string(), key.map(str::to_string))
+        })
+        .collect::<Vec<_>>();
+    if chans.is_empty() {
+        return Err("No channel name provided");
+    }
+    Ok(chans)
 }
 
 fn make_cmd(cmd: &str, rest: &str) -> Result<Cmd, &'static str> {
     match cmd {
-        "/connect" => (!rest.is_empty())
-            .then_some(Cmd::Connect(rest.to_string()))
-            .ok_or("No server address provided"),
-        "/join" => (!rest.is_empty())
-            .then_some(Cmd::Join(rest.to_string()))
-            .ok_or("No channel name provided"),
+        "/connect" => parse_serv_addr(rest).map(Cmd::Connect),
+        "/join" => parse_join(rest).map(Cmd::Join),
         "/nick" => (!rest.is_empty())
             .then_some(Cmd::Nick(rest.to_string()))
             .ok_or("No nickname provided"),
         "/quit" => Ok(Cmd::Quit(rest.to_string())),
+        "/reconnect" => Ok(Cmd::Reconnect),
+        "/names" => Ok(Cmd::Names),
+        "/topic" => Ok(Cmd::Topic((!rest.is_empty()).then(|| rest.to_string()))),
         _ => Ok(Cmd::Unsupported {
             cmd: cmd.to_string(),
             rest: rest.to_string(),
@@ -48,7 +156,126 @@ mod tests {
     fn test_parse_connect() {
         let input = "/connect irc.freenode.net";
         let cmd = parse_input(input);
-        assert_eq!(cmd, Ok(Cmd::Connect("irc.freenode.net".to_string())));
+        assert_eq!(
+            cmd,
+            Ok(Cmd::Connect(ServAddr {
+                host: "irc.freenode.net".to_string(),
+                port: 6667,
+                tls: false,
+                verify: true,
+                name: None,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_parse_connect_tls_flag() {
+        let input = "/connect -tls irc.libera.chat";
+        let cmd = parse_input(input);
+        assert_eq!(
+            cmd,
+            Ok(Cmd::Connect(ServAddr {
+                host: "irc.libera.chat".to_string(),
+                port: 6697,
+                tls: true,
+                verify: true,
+                name: None,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_parse_connect_ircs_url() {
+        let input = "/connect ircs://irc.libera.chat:7000";
+        let cmd = parse_input(input);
+        assert_eq!(
+            cmd,
+            Ok(Cmd::Connect(ServAddr {
+                host: "irc.libera.chat".to_string(),
+                port: 7000,
+                tls: true,
+                verify: true,
+                name: None,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_parse_connect_insecure() {
+        let input = "/connect -insecure localhost:6697";
+        let cmd = parse_input(input);
+        assert_eq!(
+            cmd,
+            Ok(Cmd::Connect(ServAddr {
+                host: "localhost".to_string(),
+                port: 6697,
+                tls: true,
+                verify: false,
+                name: None,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_parse_connect_name() {
+        let input = "/connect -name libera -tls irc.libera.chat";
+        let cmd = parse_input(input);
+        assert_eq!(
+            cmd,
+            Ok(Cmd::Connect(ServAddr {
+                host: "irc.libera.chat".to_string(),
+                port: 6697,
+                tls: true,
+                verify: true,
+                name: Some("libera".to_string()),
+            }))
+        );
+        let cmd = parse_input("/connect irc.libera.chat -name");
+        assert_eq!(cmd, Err("No name given to -name"));
+    }
+
+    #[test]
+    fn test_parse_connect_ipv6() {
+        let connect = |input| match parse_input(input) {
+            Ok(Cmd::Connect(addr)) => Ok((addr.host, addr.port, addr.tls)),
+            Ok(cmd) => panic!("unexpected {cmd:?}"),
+            Err(e) => Err(e),
+        };
+        assert_eq!(
+            connect("/connect ::1"),
+            Ok(("::1".to_string(), 6667, false))
+        );
+        assert_eq!(
+            connect("/connect ircs://[2001:db8::1]:6697"),
+            Ok(("2001:db8::1".to_string(), 6697, true))
+        );
+        assert_eq!(
+            connect("/connect -tls [2001:db8::1]"),
+            Ok(("2001:db8::1".to_string(), 6697, true))
+        );
+        assert_eq!(connect("/connect [::1]6697"), Err("Invalid port"));
+        assert_eq!(
+            connect("/connect [::1:6697"),
+            Err("Missing ] after IPv6 address")
+        );
+        assert_eq!(
+            connect("/connect []:6697"),
+            Err("No server address provided")
+        );
+    }
+
+    #[test]
+    fn test_parse_connect_bad_port() {
+        let input = "/connect irc.libera.chat:sixsixsixseven";
+        let cmd = parse_input(input);
+        assert_eq!(cmd, Err("Invalid port"));
+    }
+
+    #[test]
+    fn test_parse_connect_unknown_flag() {
+        let input = "/connect -ssl irc.libera.chat";
+        let cmd = parse_input(input);
+        assert_eq!(cmd, Err("Unknown /connect flag"));
     }
 
     #[test]
@@ -62,7 +289,36 @@ mod tests {
     fn test_parse_join() {
         let input = "/join #bobcat";
         let cmd = parse_input(input);
-        assert_eq!(cmd, Ok(Cmd::Join("#bobcat".to_string())));
+        assert_eq!(cmd, Ok(Cmd::Join(vec![("#bobcat".to_string(), None)])));
+    }
+
+    #[test]
+    fn test_parse_join_keys() {
+        let join = |chans: &[(&str, Option<&str>)]| {
+            Ok(Cmd::Join(
+                chans
+                    .iter()
+                    .map(|(chan, key)| (chan.to_string(), key.map(str::to_string)))
+                    .collect(),
+            ))
+        };
+        assert_eq!(
+            parse_input("/join #secret sesame"),
+            join(&[("#secret", Some("sesame"))])
+        );
+        assert_eq!(
+            parse_input("/join #a,#b,#c k1,,k3"),
+            join(&[("#a", Some("k1")), ("#b", None), ("#c", Some("k3"))])
+        );
+        assert_eq!(
+            parse_input("/join #a,#b k1"),
+            join(&[("#a", Some("k1")), ("#b", None)])
+        );
+        assert_eq!(
+            parse_input("/join #a k1 extra"),
+            Err("Too many arguments to /join")
+        );
+        assert_eq!(parse_input("/join ,"), Err("No channel name provided"));
     }
 
     #[test]
@@ -86,6 +342,13 @@ mod tests {
         assert_eq!(cmd, Ok(Cmd::Quit("".to_string())));
     }
 
+    #[test]
+    fn test_parse_reconnect() {
+        let input = "/reconnect";
+        let cmd = parse_input(input);
+        assert_eq!(cmd, Ok(Cmd::Reconnect));
+    }
+
     #[test]
     fn test_parse_nick() {
         let input = "/nick MrNickname";
@@ -100,6 +363,16 @@ mod tests {
         assert_eq!(cmd, Err("No nickname provided"));
     }
 
+    #[test]
+    fn test_parse_names_topic() {
+        assert_eq!(parse_input("/names"), Ok(Cmd::Names));
+        assert_eq!(parse_input("/topic"), Ok(Cmd::Topic(None)));
+        assert_eq!(
+            parse_input("/topic All about bobcats"),
+            Ok(Cmd::Topic(Some("All about bobcats".to_string())))
+        );
+    }
+
     #[test]
     fn test_unsupported() {
         let input = "/rhubarb jsjjsjs args";
@@ -0,0 +1,359 @@
+/// Line editing for the input bar
+use crate::wrap;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::VecDeque;
+use std::ops::Range;
+use unicode_width::UnicodeWidthChar;
+
+/// Kills kept for yanking.
+const KILL_RING_LEN: usize = 16;
+
+/// Killed text, most recent first. Shared by all tabs.
+#[derive(Debug, Default)]
+pub struct KillRing {
+    kills: VecDeque<String>,
+}
+
+impl KillRing {
+    fn push(&mut self, kill: String) {
+        if kill.is_empty() {
+            return;
+        }
+        self.kills.push_front(kill);
+        self.kills.truncate(KILL_RING_LEN);
+    }
+
+    fn get(&self, n: usize) -> Option<&str> {
+        self.kills
+            .get(n % self.kills.len().max(1))
+            .map(String::as_str)
+    }
+}
+
+/// Text being typed, with a cursor.
+#[derive(Debug, Default)]
+pub struct Editor {
+    text: String,
+    /// Byte offset, always on a grapheme boundary
+    cursor: usize,
+    /// Where the last yank went and which kill it was, for Alt-Y to swap it for an older one.
+    yank: Option<(Range<usize>, usize)>,
+    /// First byte shown when the text is too wide for the screen
+    offset: usize,
+}
+
+impl Editor {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replace the text, with the cursor at its end.
+    pub fn set(&mut self, text: String) {
+        self.cursor = text.len();
+        self.offset = 0;
+        self.yank = None;
+        self.text = text;
+    }
+
+    /// Take the text out, leaving the editor empty.
+    pub fn take(&mut self) -> String {
+        self.cursor = 0;
+        self.offset = 0;
+        self.yank = None;
+        std::mem::take(&mut self.text)
+    }
+
+    /// Apply an editing key. Returns false for keys that aren't about editing.
+    pub fn handle(&mut self, key: KeyEvent, kills: &mut KillRing) -> bool {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        let yank = self.yank.take();
+        match key.code {
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::Char('e') if ctrl => self.cursor = self.text.len(),
+            KeyCode::Char('w') if ctrl => {
+                let start = self.word_start(char::is_whitespace);
+                kills.push(self.remove(start..self.cursor));
+            }
+            KeyCode::Char('u') if ctrl => kills.push(self.remove(0..self.cursor)),
+            KeyCode::Char('k') if ctrl => kills.push(self.remove(self.cursor..self.text.len())),
+            KeyCode::Char('y') if ctrl => self.yank_kill(kills, 0),
+            KeyCode::Char('y') if alt => match yank {
+                Some((range, n)) => {
+                    self.remove(range);
+                    self.yank_kill(kills, n + 1);
+                }
+                None => return false,
+            },
+            KeyCode::Char('b') if alt => self.cursor = self.word_start(|c| !c.is_alphanumeric()),
+            KeyCode::Char('f') if alt => self.cursor = self.word_end(),
+            KeyCode::Char(_) if ctrl || alt => return false,
+            KeyCode::Char(c) => self.insert(&c.to_string()),
+            KeyCode::Backspace => {
+                let start = prev_boundary(&self.text, self.cursor);
+                self.remove(start..self.cursor);
+            }
+            KeyCode::Delete => {
+                let end = next_boundary(&self.text, self.cursor);
+                self.remove(self.cursor..end);
+            }
+            KeyCode::Left => self.cursor = prev_boundary(&self.text, self.cursor),
+            KeyCode::Right => self.cursor = next_boundary(&self.text, self.cursor),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.text.len(),
+            _ => return false,
+        }
+        true
+    }
+
+    fn insert(&mut self, text: &str) {
+        self.text.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    /// Cut out `range`, leaving the cursor where it was.
+    fn remove(&mut self, range: Range<usize>) -> String {
+        let removed = self.text.drain(range.clone()).collect();
+        if self.cursor >= range.end {
+            self.cursor -= range.len();
+        } else if self.cursor > range.start {
+            self.cursor = range.start;
+        }
+        removed
+    }
+
+    /// Insert the `n`th most recent kill.
+    fn yank_kill(&mut self, kills: &KillRing, n: usize) {
+        let Some(kill) = kills.get(n) else {
+            return;
+        };
+        let start = self.cursor;
+        self.insert(kill);
+        self.yank = Some((start..self.cursor, n));
+    }
+
+    /// Back from the cursor over separators, then over the word before them.
+    fn word_start(&self, is_separator: fn(char) -> bool) -> usize {
+        let mut pos = self.cursor;
+        let mut in_word = false;
+        while pos > 0 {
+            let prev = prev_boundary(&self.text, pos);
+            let separator = self.text[prev..].starts_with(is_separator);
+            if separator && in_word {
+                break;
+            }
+            in_word |= !separator;
+            pos = prev;
+        }
+        pos
+    }
+
+    /// Forward from the cursor over separators, then to the end of the word after them.
+    fn word_end(&self) -> usize {
+        let mut pos = self.cursor;
+        let mut in_word = false;
+        while pos < self.text.len() {
+            let separator = !self.text[pos..].starts_with(char::is_alphanumeric);
+            if separator && in_word {
+                break;
+            }
+            in_word |= !separator;
+            pos = next_boundary(&self.text, pos);
+        }
+        pos
+    }
+
+    /// The part of the text to show in `width` columns, and the column of the cursor. Scrolls
+    /// sideways to keep the cursor in view.
+    pub fn view(&mut self, width: usize) -> (&str, usize) {
+        let width = width.max(1);
+        self.offset = self.offset.min(self.cursor);
+        while wrap::width(&self.text[self.offset..self.cursor]) >= width {
+            self.offset = next_boundary(&self.text, self.offset);
+        }
+        let mut end = self.offset;
+        while end < self.text.len() {
+            let next = next_boundary(&self.text, end);
+            if wrap::width(&self.text[self.offset..next]) > width {
+                break;
+            }
+            end = next;
+        }
+        let cursor = wrap::width(&self.text[self.offset..self.cursor]);
+        (&self.text[self.offset..end], cursor)
+    }
+}
+
+const ZWJ: char = '\u{200d}';
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1f1e6}'..='\u{1f1ff}').contains(&c)
+}
+
+/// Where the grapheme starting at `pos` ends. Close enough to Unicode's extended grapheme
+/// clusters for editing: combining marks, ZWJ sequences and flags stay in one piece.
+fn next_boundary(text: &str, pos: usize) -> usize {
+    let mut chars = text[pos..].char_indices().peekable();
+    let Some((_, first)) = chars.next() else {
+        return pos;
+    };
+    let mut prev = first;
+    let mut flag = is_regional_indicator(first);
+    while let Some(&(i, c)) = chars.peek() {
+        let joins = c.width() == Some(0) || prev == ZWJ || (flag && is_regional_indicator(c));
+        if !joins {
+            return pos + i;
+        }
+        flag = false;
+        prev = c;
+        chars.next();
+    }
+    text.len()
+}
+
+/// Where the grapheme ending at `pos` starts.
+fn prev_boundary(text: &str, pos: usize) -> usize {
+    // Graphemes are found going forward, so walk them from the start.
+    let mut start = 0;
+    while start < pos {
+        let next = next_boundary(text, start);
+        if next >= pos {
+            break;
+        }
+        start = next;
+    }
+    start
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn ctrl(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    fn alt(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::ALT)
+    }
+
+    fn typed(text: &str, kills: &mut KillRing) -> Editor {
+        let mut editor = Editor::default();
+        for c in text.chars() {
+            editor.handle(key(KeyCode::Char(c)), kills);
+        }
+        editor
+    }
+
+    /// The text with a `|` at the cursor.
+    fn show(editor: &Editor) -> String {
+        let mut text = editor.text.clone();
+        text.insert(editor.cursor, '|');
+        text
+    }
+
+    #[test]
+    fn test_cursor() {
+        let mut kills = KillRing::default();
+        let mut editor = typed("hello", &mut kills);
+        editor.handle(key(KeyCode::Left), &mut kills);
+        editor.handle(key(KeyCode::Left), &mut kills);
+        editor.handle(key(KeyCode::Char('X')), &mut kills);
+        assert_eq!(show(&editor), "helX|lo");
+        editor.handle(key(KeyCode::Delete), &mut kills);
+        editor.handle(key(KeyCode::Backspace), &mut kills);
+        assert_eq!(show(&editor), "hel|o");
+        editor.handle(ctrl('a'), &mut kills);
+        assert_eq!(show(&editor), "|helo");
+        editor.handle(key(KeyCode::Backspace), &mut kills);
+        editor.handle(key(KeyCode::End), &mut kills);
+        editor.handle(key(KeyCode::Right), &mut kills);
+        assert_eq!(show(&editor), "helo|");
+        assert!(!editor.handle(key(KeyCode::Enter), &mut kills));
+        assert!(!editor.handle(ctrl('r'), &mut kills));
+        assert_eq!(editor.take(), "helo");
+        assert_eq!(show(&editor), "|");
+    }
+
+    #[test]
+    fn test_graphemes() {
+        let mut kills = KillRing::default();
+        // e + combining acute, a family joined with ZWJs, a flag
+        let mut editor = typed("e\u{301}👨\u{200d}👩\u{200d}👧🇫🇷x", &mut kills);
+        editor.handle(key(KeyCode::Left), &mut kills);
+        editor.handle(key(KeyCode::Left), &mut kills);
+        assert_eq!(show(&editor), "e\u{301}👨\u{200d}👩\u{200d}👧|🇫🇷x");
+        editor.handle(key(KeyCode::Backspace), &mut kills);
+        assert_eq!(show(&editor), "e\u{301}|🇫🇷x");
+        editor.handle(key(KeyCode::Backspace), &mut kills);
+        assert_eq!(show(&editor), "|🇫🇷x");
+        editor.handle(key(KeyCode::Delete), &mut kills);
+        assert_eq!(show(&editor), "|x");
+    }
+
+    #[test]
+    fn test_words() {
+        let mut kills = KillRing::default();
+        let mut editor = typed("one two-three  four", &mut kills);
+        editor.handle(alt('b'), &mut kills);
+        assert_eq!(show(&editor), "one two-three  |four");
+        editor.handle(alt('b'), &mut kills);
+        assert_eq!(show(&editor), "one two-|three  four");
+        editor.handle(alt('f'), &mut kills);
+        assert_eq!(show(&editor), "one two-three|  four");
+        editor.handle(alt('f'), &mut kills);
+        assert_eq!(show(&editor), "one two-three  four|");
+        // Ctrl-W goes by whitespace.
+        editor.handle(key(KeyCode::Left), &mut kills);
+        editor.handle(ctrl('w'), &mut kills);
+        assert_eq!(show(&editor), "one two-three  |r");
+        editor.handle(ctrl('w'), &mut kills);
+        assert_eq!(show(&editor), "one |r");
+    }
+
+    #[test]
+    fn test_kill_ring() {
+        let mut kills = KillRing::default();
+        let mut editor = typed("one two three", &mut kills);
+        editor.handle(ctrl('w'), &mut kills);
+        editor.handle(ctrl('a'), &mut kills);
+        editor.handle(ctrl('k'), &mut kills);
+        assert_eq!(show(&editor), "|");
+        editor.handle(ctrl('y'), &mut kills);
+        assert_eq!(show(&editor), "one two |");
+        editor.handle(alt('y'), &mut kills);
+        assert_eq!(show(&editor), "three|");
+        // Back round to the most recent
+        editor.handle(alt('y'), &mut kills);
+        assert_eq!(show(&editor), "one two |");
+        // Only right after a yank
+        editor.handle(key(KeyCode::Left), &mut kills);
+        assert!(!editor.handle(alt('y'), &mut kills));
+
+        editor.handle(ctrl('u'), &mut kills);
+        assert_eq!(show(&editor), "| ");
+        editor.handle(ctrl('y'), &mut kills);
+        assert_eq!(show(&editor), "one two| ");
+    }
+
+    #[test]
+    fn test_view() {
+        let mut kills = KillRing::default();
+        let mut editor = typed("abcdefghij", &mut kills);
+        // Room for the cursor after the text
+        assert_eq!(editor.view(5), ("ghij", 4));
+        editor.handle(key(KeyCode::Home), &mut kills);
+        assert_eq!(editor.view(5), ("abcde", 0));
+        editor.handle(key(KeyCode::End), &mut kills);
+        editor.handle(key(KeyCode::Left), &mut kills);
+        editor.handle(key(KeyCode::Left), &mut kills);
+        assert_eq!(editor.view(5), ("efghi", 4));
+
+        let mut editor = typed("日本語", &mut kills);
+        assert_eq!(editor.view(4), ("語", 2));
+    }
+}
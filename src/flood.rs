@@ -0,0 +1,172 @@
+/// Outgoing flood control
+use crate::protocol::ClientMsg;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Token bucket settings: up to `burst` messages back to back, then `rate` messages per second.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flood {
+    pub burst: u32,
+    pub rate: f64,
+}
+
+impl Default for Flood {
+    fn default() -> Self {
+        Self {
+            burst: 5,
+            rate: 0.5,
+        }
+    }
+}
+
+/// Messages waiting for the rate limit to let them through. PONG and QUIT jump the line.
+#[derive(Debug)]
+pub struct SendQueue {
+    flood: Flood,
+    tokens: f64,
+    /// When `tokens` was last topped up.
+    refilled: Instant,
+    msgs: VecDeque<ClientMsg>,
+}
+
+impl SendQueue {
+    /// Start with a full bucket.
+    pub fn new(flood: Flood, now: Instant) -> Self {
+        Self {
+            tokens: flood.burst as f64,
+            flood,
+            refilled: now,
+            msgs: VecDeque::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.msgs.len()
+    }
+
+    pub fn push(&mut self, msg: ClientMsg) {
+        if is_urgent(&msg) {
+            // Behind other urgent messages, ahead of everything else.
+            let pos = self.msgs.iter().take_while(|msg| is_urgent(msg)).count();
+            self.msgs.insert(pos, msg);
+        } else {
+            self.msgs.push_back(msg);
+        }
+    }
+
+    /// The next message, if the bucket has a token for it.
+    pub fn pop(&mut self, now: Instant) -> Option<ClientMsg> {
+        self.refill(now);
+        if self.msgs.is_empty() || self.tokens < 1.0 {
+            return None;
+        }
+        self.tokens -= 1.0;
+        self.msgs.pop_front()
+    }
+
+    /// When `pop` will have something to give, or `None` if nothing is queued.
+    pub fn next_ready(&self, now: Instant) -> Option<Instant> {
+        if self.msgs.is_empty() {
+            return None;
+        }
+        let tokens = self.tokens_at(now);
+        if tokens >= 1.0 {
+            return Some(now);
+        }
+        Some(now + Duration::from_secs_f64((1.0 - tokens) / self.flood.rate))
+    }
+
+    fn refill(&mut self, now: Instant) {
+        self.tokens = self.tokens_at(now);
+        self.refilled = now;
+    }
+
+    fn tokens_at(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        (self.tokens + elapsed * self.flood.rate).min(self.flood.burst as f64)
+    }
+}
+
+/// Late PONGs get us disconnected, and a QUIT shouldn't wait behind a long paste.
+fn is_urgent(msg: &ClientMsg) -> bool {
+    matches!(msg, ClientMsg::Pong { .. } | ClientMsg::Quit { .. })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn privmsg(msg: &str) -> ClientMsg {
+        ClientMsg::PrivMsg {
+            target: "#bobcat".to_string(),
+            msg: msg.to_string(),
+        }
+    }
+
+    fn queue(burst: u32, rate: f64, now: Instant) -> SendQueue {
+        SendQueue::new(Flood { burst, rate }, now)
+    }
+
+    #[test]
+    fn test_burst_then_rate() {
+        let now = Instant::now();
+        let mut queue = queue(2, 1.0, now);
+        for i in 0..4 {
+            queue.push(privmsg(&i.to_string()));
+        }
+        assert_eq!(queue.pop(now), Some(privmsg("0")));
+        assert_eq!(queue.pop(now), Some(privmsg("1")));
+        assert_eq!(queue.pop(now), None);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.next_ready(now), Some(now + Duration::from_secs(1)));
+
+        let later = now + Duration::from_millis(500);
+        assert_eq!(queue.pop(later), None);
+        assert_eq!(
+            queue.next_ready(later),
+            Some(later + Duration::from_millis(500))
+        );
+        let later = now + Duration::from_secs(1);
+        assert_eq!(queue.pop(later), Some(privmsg("2")));
+        assert_eq!(queue.pop(later), None);
+    }
+
+    #[test]
+    fn test_refill_capped_at_burst() {
+        let now = Instant::now();
+        let mut queue = queue(2, 1.0, now);
+        let later = now + Duration::from_secs(60);
+        for i in 0..3 {
+            queue.push(privmsg(&i.to_string()));
+        }
+        assert!(queue.pop(later).is_some());
+        assert!(queue.pop(later).is_some());
+        assert_eq!(queue.pop(later), None);
+    }
+
+    #[test]
+    fn test_urgent_first() {
+        let now = Instant::now();
+        let mut queue = queue(1, 1.0, now);
+        let pong = ClientMsg::Pong {
+            token: "abc".to_string(),
+        };
+        let quit = ClientMsg::Quit {
+            msg: "bye".to_string(),
+        };
+        queue.push(privmsg("a"));
+        queue.push(privmsg("b"));
+        queue.push(pong.clone());
+        queue.push(quit.clone());
+        assert_eq!(queue.pop(now), Some(pong));
+        let later = now + Duration::from_secs(1);
+        assert_eq!(queue.pop(later), Some(quit));
+    }
+
+    #[test]
+    fn test_empty_is_never_ready() {
+        let now = Instant::now();
+        let queue = queue(1, 1.0, now);
+        assert_eq!(queue.next_ready(now), None);
+    }
+}
@@ -0,0 +1,261 @@
+/// Lines typed into the input bar, for recalling and searching
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Lines kept for each tab
+pub const TAB_HISTORY_LEN: usize = 100;
+/// Lines kept across all tabs, and in the history file
+pub const GLOBAL_HISTORY_LEN: usize = 1000;
+
+/// Committed lines, most recent first.
+#[derive(Debug)]
+pub struct History {
+    lines: VecDeque<String>,
+    max: usize,
+}
+
+impl History {
+    pub fn new(max: usize) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            max,
+        }
+    }
+
+    /// Remember a line, unless it's blank or the same as the last one.
+    pub fn push(&mut self, line: &str) {
+        if line.trim().is_empty() || self.get(0) == Some(line) {
+            return;
+        }
+        self.lines.push_front(line.to_string());
+        self.lines.truncate(self.max);
+    }
+
+    /// The `n`th most recent line.
+    pub fn get(&self, n: usize) -> Option<&str> {
+        self.lines.get(n).map(String::as_str)
+    }
+
+    /// The first line from the `n`th most recent back that contains `query`.
+    fn find(&self, query: &str, n: usize) -> Option<usize> {
+        (n..self.lines.len()).find(|&i| self.lines[i].contains(query))
+    }
+
+    /// Read a history file, one line per line, oldest first.
+    pub fn load(path: &Path, max: usize) -> io::Result<Self> {
+        let mut history = History::new(max);
+        for line in std::fs::read_to_string(path)?.lines() {
+            history.push(line);
+        }
+        Ok(history)
+    }
+
+    /// Write the history out, readable only by us since it can hold passwords.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(path)?;
+        // The mode only applies to new files.
+        #[cfg(unix)]
+        file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+        let mut file = io::BufWriter::new(file);
+        for line in self.lines.iter().rev() {
+            writeln!(file, "{line}")?;
+        }
+        file.flush()
+    }
+}
+
+/// How far Up and Down have gone back through a history, and what was typed before.
+#[derive(Debug, Default)]
+pub struct Recall {
+    pos: Option<usize>,
+    /// Going through the global history rather than the tab's
+    global: bool,
+    draft: String,
+}
+
+impl Recall {
+    /// The line before the one shown. `current` is kept as the draft when starting out.
+    pub fn older<'a>(
+        &mut self,
+        history: &'a History,
+        global: bool,
+        current: &str,
+    ) -> Option<&'a str> {
+        let n = match self.pos {
+            Some(n) if self.global == global => n + 1,
+            _ => 0,
+        };
+        let line = history.get(n)?;
+        if self.pos.is_none() {
+            self.draft = current.to_string();
+        }
+        self.pos = Some(n);
+        self.global = global;
+        Some(line)
+    }
+
+    /// The line after the one shown, ending with the draft.
+    pub fn newer(&mut self, history: &History, global: bool) -> Option<String> {
+        let n = self.pos.filter(|_| self.global == global)?;
+        if n == 0 {
+            self.pos = None;
+            return Some(std::mem::take(&mut self.draft));
+        }
+        self.pos = Some(n - 1);
+        history.get(n - 1).map(str::to_string)
+    }
+
+    pub fn reset(&mut self) {
+        self.pos = None;
+        self.draft.clear();
+    }
+}
+
+/// A reverse incremental search, as with Ctrl-R in a shell.
+#[derive(Debug, Default)]
+pub struct Search {
+    query: String,
+    found: Option<usize>,
+    /// Nothing (older) matches the query
+    failed: bool,
+}
+
+impl Search {
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn found<'a>(&self, history: &'a History) -> Option<&'a str> {
+        self.found.and_then(|n| history.get(n))
+    }
+
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    /// Search for `query` from the `n`th line back, staying on the last match if there's none.
+    fn find(&mut self, history: &History, n: usize) {
+        match history.find(&self.query, n) {
+            Some(found) => {
+                self.found = Some(found);
+                self.failed = false;
+            }
+            None => self.failed = true,
+        }
+    }
+
+    pub fn push(&mut self, c: char, history: &History) {
+        self.query.push(c);
+        self.find(history, self.found.unwrap_or(0));
+    }
+
+    /// Shorten the query, going back to the most recent match.
+    pub fn pop(&mut self, history: &History) {
+        self.query.pop();
+        self.found = None;
+        self.find(history, 0);
+    }
+
+    /// The next older match.
+    pub fn next(&mut self, history: &History) {
+        match self.found {
+            Some(n) => self.find(history, n + 1),
+            None => self.find(history, 0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(lines: &[&str]) -> History {
+        let mut history = History::new(10);
+        for line in lines {
+            history.push(line);
+        }
+        history
+    }
+
+    #[test]
+    fn test_push() {
+        let mut history = history(&["one", "two", "two", " ", "three"]);
+        assert_eq!(history.get(0), Some("three"));
+        assert_eq!(history.get(1), Some("two"));
+        assert_eq!(history.get(2), Some("one"));
+        assert_eq!(history.get(3), None);
+
+        history.max = 2;
+        history.push("four");
+        assert_eq!(history.get(1), Some("three"));
+        assert_eq!(history.get(2), None);
+    }
+
+    #[test]
+    fn test_recall() {
+        let tab = history(&["one", "two"]);
+        let global = history(&["one", "elsewhere", "two"]);
+        let mut recall = Recall::default();
+        assert_eq!(recall.newer(&tab, false), None);
+        assert_eq!(recall.older(&tab, false, "draft"), Some("two"));
+        assert_eq!(recall.older(&tab, false, "two"), Some("one"));
+        assert_eq!(recall.older(&tab, false, "one"), None);
+        assert_eq!(recall.newer(&tab, false).as_deref(), Some("two"));
+        // Switching to the global history starts over from its latest line.
+        assert_eq!(recall.older(&global, true, "two"), Some("two"));
+        assert_eq!(recall.older(&global, true, "two"), Some("elsewhere"));
+        assert_eq!(recall.newer(&tab, false), None);
+        assert_eq!(recall.newer(&global, true).as_deref(), Some("two"));
+        assert_eq!(recall.newer(&global, true).as_deref(), Some("draft"));
+        assert_eq!(recall.newer(&global, true), None);
+
+        recall.older(&tab, false, "again");
+        recall.reset();
+        assert_eq!(recall.older(&tab, false, ""), Some("two"));
+        assert_eq!(recall.newer(&tab, false).as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_search() {
+        let history = history(&["/join #rust", "hello", "/join #irc", "bye"]);
+        let mut search = Search::default();
+        for c in "/jo".chars() {
+            search.push(c, &history);
+        }
+        assert_eq!(search.found(&history), Some("/join #irc"));
+        search.next(&history);
+        assert_eq!(search.found(&history), Some("/join #rust"));
+        search.next(&history);
+        assert!(search.failed());
+        assert_eq!(search.found(&history), Some("/join #rust"));
+        search.pop(&history);
+        assert!(!search.failed());
+        assert_eq!(search.found(&history), Some("/join #irc"));
+        search.push('x', &history);
+        assert!(search.failed());
+        assert_eq!(search.query(), "/jx");
+    }
+
+    #[test]
+    fn test_save_load() {
+        let path = std::env::temp_dir().join(format!("irc-history-{}", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        history(&["one", "two", "three"]).save(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let history = History::load(&path, 2).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(history.get(0), Some("three"));
+        assert_eq!(history.get(1), Some("two"));
+        assert_eq!(history.get(2), None);
+    }
+}
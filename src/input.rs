@@ -1,23 +1,45 @@
-use crossterm::event::{Event, EventStream, KeyCode};
+use crossterm::event::{Event, EventStream, KeyEvent, KeyEventKind, MouseEventKind};
 use futures::StreamExt;
 use tokio::sync::mpsc::{Receiver, Sender};
 
-pub fn listen() -> Receiver<KeyCode> {
+/// Terminal input the UI acts on.
+#[derive(Debug)]
+pub enum Input {
+    Key(KeyEvent),
+    /// Mouse wheel, towards older lines or not.
+    Scroll {
+        up: bool,
+    },
+    /// The terminal is now this many columns and rows.
+    Resize {
+        cols: u16,
+        rows: u16,
+    },
+}
+
+pub fn listen() -> Receiver<Input> {
     let (tx, rx) = tokio::sync::mpsc::channel(100);
     tokio::task::spawn_local(poll_event_stream(tx));
     rx
 }
 
-async fn poll_event_stream(input_tx: Sender<KeyCode>) {
+async fn poll_event_stream(input_tx: Sender<Input>) {
     let mut reader = EventStream::new();
     loop {
-        match reader.next().await {
-            Some(Ok(Event::Key(key_ev))) => {
-                input_tx.send(key_ev.code).await.unwrap();
+        let input = match reader.next().await {
+            Some(Ok(Event::Key(key_ev))) if key_ev.kind != KeyEventKind::Release => {
+                Input::Key(key_ev)
             }
-            Some(Ok(_)) => {}
+            Some(Ok(Event::Mouse(mouse_ev))) => match mouse_ev.kind {
+                MouseEventKind::ScrollUp => Input::Scroll { up: true },
+                MouseEventKind::ScrollDown => Input::Scroll { up: false },
+                _ => continue,
+            },
+            Some(Ok(Event::Resize(cols, rows))) => Input::Resize { cols, rows },
+            Some(Ok(_)) => continue,
             Some(Err(e)) => panic!("input::poll_event_stream(): {e}"),
-            None => {} // ??
-        }
+            None => continue, // ??
+        };
+        input_tx.send(input).await.unwrap();
     }
 }
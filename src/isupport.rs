@@ -0,0 +1,199 @@
+/// Server features from RPL_ISUPPORT (005)
+use crate::casemap::CaseMapping;
+use crate::mode::ChanModes;
+use std::collections::BTreeMap;
+
+/// What the server told us about itself. See https://modern.ircdocs.horse/#rplisupport-parameters
+#[derive(Debug, Clone, Default)]
+pub struct ISupport {
+    /// Unescaped values by name. Tokens without a value have an empty one.
+    tokens: BTreeMap<String, String>,
+    /// From PREFIX and CHANMODES, kept parsed since every MODE needs it.
+    chanmodes: ChanModes,
+}
+
+impl ISupport {
+    /// Take in the tokens of a 005. Later tokens override earlier ones, and `-NAME` drops one.
+    pub fn apply(&mut self, tokens: &[String]) {
+        for token in tokens {
+            let name = match token.strip_prefix('-') {
+                Some(name) => {
+                    self.tokens.remove(name);
+                    name
+                }
+                None => {
+                    let (name, value) = token.split_once('=').unwrap_or((token, ""));
+                    self.tokens.insert(name.to_string(), unescape(value));
+                    name
+                }
+            };
+            if name == "PREFIX" || name == "CHANMODES" {
+                self.update_chanmodes();
+            }
+        }
+    }
+
+    fn update_chanmodes(&mut self) {
+        let mut chanmodes = ChanModes::default();
+        if let Some(prefix) = self.get("PREFIX") {
+            chanmodes.set_prefix(prefix);
+        }
+        if let Some(groups) = self.get("CHANMODES") {
+            chanmodes.set_chanmodes(groups);
+        }
+        self.chanmodes = chanmodes;
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.tokens.get(name).map(String::as_str)
+    }
+
+    /// A number, if the server gave a valid one.
+    fn number(&self, name: &str) -> Option<usize> {
+        self.get(name)?.parse().ok()
+    }
+
+    pub fn casemapping(&self) -> CaseMapping {
+        self.get("CASEMAPPING")
+            .map(CaseMapping::from_isupport)
+            .unwrap_or_default()
+    }
+
+    /// Characters channel names start with.
+    pub fn chantypes(&self) -> &str {
+        self.get("CHANTYPES").unwrap_or("#&")
+    }
+
+    pub fn is_channel(&self, target: &str) -> bool {
+        target.starts_with(|c| self.chantypes().contains(c))
+    }
+
+    pub fn chanmodes(&self) -> &ChanModes {
+        &self.chanmodes
+    }
+
+    pub fn nicklen(&self) -> Option<usize> {
+        self.number("NICKLEN")
+    }
+
+    pub fn topiclen(&self) -> Option<usize> {
+        self.number("TOPICLEN")
+    }
+
+    /// How many targets `cmd` takes at once: `None` if TARGMAX doesn't list it, `Some(None)` if
+    /// it does without a limit.
+    pub fn targmax(&self, cmd: &str) -> Option<Option<usize>> {
+        self.get("TARGMAX")?
+            .split(',')
+            .filter_map(|limit| limit.split_once(':'))
+            .find(|(name, _)| name.eq_ignore_ascii_case(cmd))
+            .map(|(_, max)| max.parse().ok())
+    }
+
+    /// The name of the network the server is part of.
+    pub fn network(&self) -> Option<&str> {
+        self.get("NETWORK").filter(|network| !network.is_empty())
+    }
+
+    /// Whether the server supports MONITOR.
+    pub fn monitor(&self) -> bool {
+        self.tokens.contains_key("MONITOR")
+    }
+}
+
+/// Values escape bytes as `\xHH`.
+fn unescape(value: &str) -> String {
+    let mut bytes = vec![];
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .strip_prefix(b"x")
+            .and_then(|hex| hex.get(..2))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(escaped) if byte == b'\\' => {
+                bytes.push(escaped);
+                rest = &tail[3..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mode::ArgKind;
+
+    fn isupport(tokens: &[&str]) -> ISupport {
+        let mut isupport = ISupport::default();
+        isupport.apply(&tokens.iter().map(|t| t.to_string()).collect::<Vec<_>>());
+        isupport
+    }
+
+    #[test]
+    fn test_defaults() {
+        let isupport = ISupport::default();
+        assert!(isupport.is_channel("#bobcat"));
+        assert!(isupport.is_channel("&local"));
+        assert!(!isupport.is_channel("MrNickname"));
+        assert_eq!(isupport.nicklen(), None);
+        assert_eq!(isupport.topiclen(), None);
+        assert_eq!(isupport.targmax("JOIN"), None);
+        assert!(!isupport.monitor());
+        assert_eq!(isupport.casemapping(), CaseMapping::Rfc1459);
+        assert_eq!(isupport.network(), None);
+    }
+
+    #[test]
+    fn test_tokens() {
+        let isupport = isupport(&[
+            "CASEMAPPING=ascii",
+            "CHANTYPES=#",
+            "NICKLEN=30",
+            "MONITOR",
+            r"NETWORK=Example\x20Network",
+            "PREFIX=(ov)@+",
+            "CHANMODES=b,k,l,imnst",
+        ]);
+        assert!(!isupport.is_channel("&local"));
+        assert_eq!(isupport.casemapping(), CaseMapping::Ascii);
+        assert_eq!(isupport.nicklen(), Some(30));
+        assert!(isupport.monitor());
+        assert_eq!(isupport.network(), Some("Example Network"));
+        assert_eq!(isupport.chanmodes().kind('h'), ArgKind::Never);
+        assert_eq!(isupport.chanmodes().kind('o'), ArgKind::Prefix('@'));
+    }
+
+    #[test]
+    fn test_targmax() {
+        let isupport = isupport(&["TARGMAX=NAMES:1,PRIVMSG:4,ACCEPT:,MONITOR:", "TOPICLEN=390"]);
+        assert_eq!(isupport.topiclen(), Some(390));
+        assert_eq!(isupport.targmax("NAMES"), Some(Some(1)));
+        assert_eq!(isupport.targmax("privmsg"), Some(Some(4)));
+        assert_eq!(isupport.targmax("ACCEPT"), Some(None));
+        assert_eq!(isupport.targmax("MONITOR"), Some(None));
+        assert_eq!(isupport.targmax("JOIN"), None);
+    }
+
+    #[test]
+    fn test_removal() {
+        let mut isupport = isupport(&["MONITOR=100", "PREFIX=(ov)@+"]);
+        isupport.apply(&["-MONITOR".to_string(), "-PREFIX".to_string()]);
+        assert!(!isupport.monitor());
+        assert_eq!(isupport.chanmodes().kind('h'), ArgKind::Prefix('%'));
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape(r"Example\x20Network\x3D"), "Example Network=");
+        assert_eq!(unescape(r"\xE2\x98\x83"), "☃");
+        // Not escapes
+        assert_eq!(unescape(r"a\b\x2\xzz\"), r"a\b\x2\xzz\");
+    }
+}
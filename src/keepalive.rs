@@ -0,0 +1,136 @@
+/// Client-side pings: lag measurement and dead connection detection
+use std::time::{Duration, Instant};
+
+/// Ping the server every `interval`, and give up on the connection if a ping goes unanswered
+/// for `timeout`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keepalive {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            timeout: Duration::from_secs(120),
+        }
+    }
+}
+
+/// What to do when the deadline from `Pinger::deadline` comes up.
+#[derive(Debug, PartialEq)]
+pub enum Tick {
+    /// Nothing yet.
+    Wait,
+    /// Send a PING with this token.
+    Ping(String),
+    /// The last ping went unanswered for too long.
+    TimedOut,
+}
+
+/// Pings for one connection. At most one ping is in flight at a time.
+#[derive(Debug)]
+pub struct Pinger {
+    keepalive: Keepalive,
+    next_ping: Instant,
+    /// Token and send time of the ping we're waiting on.
+    pending: Option<(String, Instant)>,
+    sent: u32,
+}
+
+impl Pinger {
+    pub fn new(keepalive: Keepalive, now: Instant) -> Self {
+        Self {
+            next_ping: now + keepalive.interval,
+            keepalive,
+            pending: None,
+            sent: 0,
+        }
+    }
+
+    /// When `tick` will have something to do.
+    pub fn deadline(&self) -> Instant {
+        match &self.pending {
+            Some((_, sent)) => *sent + self.keepalive.timeout,
+            None => self.next_ping,
+        }
+    }
+
+    pub fn tick(&mut self, now: Instant) -> Tick {
+        if now < self.deadline() {
+            return Tick::Wait;
+        }
+        if self.pending.is_some() {
+            return Tick::TimedOut;
+        }
+        self.sent += 1;
+        // Tokens only need to be unique within a connection.
+        let token = format!("lag-{}", self.sent);
+        self.pending = Some((token.clone(), now));
+        Tick::Ping(token)
+    }
+
+    /// The lag, if `token` answers our ping. Other PONGs are none of our business.
+    pub fn pong(&mut self, token: &str, now: Instant) -> Option<Duration> {
+        match &self.pending {
+            Some((pending, sent)) if pending == token => {
+                let lag = now.saturating_duration_since(*sent);
+                self.pending = None;
+                self.next_ping = now + self.keepalive.interval;
+                Some(lag)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pinger(now: Instant) -> Pinger {
+        let keepalive = Keepalive {
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(30),
+        };
+        Pinger::new(keepalive, now)
+    }
+
+    #[test]
+    fn test_ping_then_lag() {
+        let now = Instant::now();
+        let mut pinger = pinger(now);
+        assert_eq!(pinger.tick(now), Tick::Wait);
+        let later = now + Duration::from_secs(10);
+        assert_eq!(pinger.deadline(), later);
+        assert_eq!(pinger.tick(later), Tick::Ping("lag-1".to_string()));
+
+        let answered = later + Duration::from_millis(300);
+        assert_eq!(pinger.pong("other", answered), None);
+        assert_eq!(
+            pinger.pong("lag-1", answered),
+            Some(Duration::from_millis(300))
+        );
+        // Already answered.
+        assert_eq!(pinger.pong("lag-1", answered), None);
+
+        assert_eq!(pinger.deadline(), answered + Duration::from_secs(10));
+        assert_eq!(
+            pinger.tick(answered + Duration::from_secs(10)),
+            Tick::Ping("lag-2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        let now = Instant::now();
+        let mut pinger = pinger(now);
+        let sent = now + Duration::from_secs(10);
+        assert!(matches!(pinger.tick(sent), Tick::Ping(_)));
+        // No second ping while the first is unanswered.
+        assert_eq!(pinger.tick(sent + Duration::from_secs(20)), Tick::Wait);
+        assert_eq!(pinger.deadline(), sent + Duration::from_secs(30));
+        assert_eq!(pinger.tick(sent + Duration::from_secs(30)), Tick::TimedOut);
+    }
+}
@@ -0,0 +1,2 @@
+//! The IRC protocol parser, exposed as a library so it can be fuzzed outside the client.
+pub mod protocol;
@@ -1,14 +1,36 @@
+use crate::backoff::Backoff;
+use crate::flood::Flood;
+use crate::keepalive::Keepalive;
+use crate::sasl::{Mechanism, SaslConfig};
 use crate::ui::UI;
 use anyhow::Result;
+use chrono::format::StrftimeItems;
+use irc::protocol;
 use std::cell::RefCell;
+use std::path::PathBuf;
 use std::rc::Rc;
+use std::time::Duration;
 
+mod backoff;
+mod cap;
+mod casemap;
+mod channel;
 mod client;
 mod command;
+mod editor;
+mod flood;
+mod history;
 mod input;
-mod protocol;
+mod isupport;
+mod keepalive;
+mod mode;
+mod nick;
+mod sasl;
+mod scram;
 mod terminal;
+mod tls;
 mod ui;
+mod wrap;
 
 fn main() -> Result<()> {
     terminal::setup()?;
@@ -18,33 +40,60 @@ fn main() -> Result<()> {
         .enable_all()
         .build()?;
     let local_set = tokio::task::LocalSet::new();
+    let size = crossterm::terminal::size()?;
 
-    local_set.block_on(&runtime, async {
+    let saved = local_set.block_on(&runtime, async {
         let input_rx = input::listen();
 
-        let tui = UI::new(config.clone());
+        let tui = UI::new(config.clone(), size);
         tui.draw();
 
         let clients = vec![];
-        ui::run(tui, input_rx, clients).await;
+        ui::run(tui, input_rx, clients).await
     });
 
     terminal::restore()?;
+    saved?;
     Ok(())
 }
 
 struct Config {
     pub nick: String,
+    /// Nicks to fall back on if `nick` is taken
+    pub alt_nicks: Vec<String>,
     pub user: String,
     pub real: String,
+    pub backoff: Backoff,
+    pub flood: Flood,
+    pub keepalive: Keepalive,
+    pub tls_cert: Option<PathBuf>,
+    pub sasl: Option<SaslConfig>,
+    /// Hosts the SASL credentials are for. Empty for any host.
+    pub sasl_hosts: Vec<String>,
+    /// Send SASL passwords to servers whose certificate isn't verified.
+    pub sasl_insecure: bool,
+    /// strftime-style format for message timestamps
+    pub time_format: String,
+    /// Where to keep input history between runs
+    pub history_file: Option<PathBuf>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             nick: "meager-irc-client".to_string(),
+            alt_nicks: vec![],
             user: "guest".to_string(),
             real: "Meager".to_string(),
+            backoff: Backoff::default(),
+            flood: Flood::default(),
+            keepalive: Keepalive::default(),
+            tls_cert: None,
+            sasl: None,
+            sasl_hosts: vec![],
+            sasl_insecure: false,
+            time_format: "%H:%M".to_string(),
+            history_file: None,
         }
     }
 }
@@ -55,12 +104,84 @@ impl Config {
         std::env::var("IRC_NICK")
             .map(|nick| config.nick = nick)
             .ok();
+        std::env::var("IRC_ALT_NICKS")
+            .map(|nicks| config.alt_nicks = nicks.split(',').map(str::to_string).collect())
+            .ok();
         std::env::var("IRC_USER")
             .map(|user| config.user = user)
             .ok();
         std::env::var("IRC_REAL")
             .map(|real| config.real = real)
             .ok();
+        if let Some(secs) = env_parse("IRC_RECONNECT_MIN") {
+            config.backoff.initial = secs_duration(secs);
+        }
+        if let Some(secs) = env_parse("IRC_RECONNECT_MAX") {
+            config.backoff.max = secs_duration(secs);
+        }
+        if let Some(jitter) = env_parse("IRC_RECONNECT_JITTER") {
+            config.backoff.jitter = jitter;
+        }
+        if let Some(burst) = env_parse::<u32>("IRC_FLOOD_BURST").filter(|&burst| burst > 0) {
+            config.flood.burst = burst;
+        }
+        if let Some(rate) = env_parse::<f64>("IRC_FLOOD_RATE").filter(|&rate| rate > 0.0) {
+            config.flood.rate = rate;
+        }
+        if let Some(secs) = env_parse::<f64>("IRC_PING_INTERVAL").filter(|&secs| secs > 0.0) {
+            config.keepalive.interval = secs_duration(secs);
+        }
+        if let Some(secs) = env_parse::<f64>("IRC_PING_TIMEOUT").filter(|&secs| secs > 0.0) {
+            config.keepalive.timeout = secs_duration(secs);
+        }
+        config.tls_cert = std::env::var_os("IRC_TLS_CERT").map(PathBuf::from);
+        config.history_file = std::env::var_os("IRC_HISTORY_FILE").map(PathBuf::from);
+        config.sasl = sasl_from_env();
+        std::env::var("IRC_SASL_HOSTS")
+            .map(|hosts| config.sasl_hosts = hosts.split(',').map(str::to_string).collect())
+            .ok();
+        config.sasl_insecure = std::env::var("IRC_SASL_INSECURE").is_ok_and(|val| val == "1");
+        // An invalid format would panic when drawing, so keep the default instead.
+        if let Ok(format) = std::env::var("IRC_TIME_FORMAT") {
+            if StrftimeItems::new(&format).parse().is_ok() {
+                config.time_format = format;
+            }
+        }
         config
     }
+
+    /// The SASL credentials for `host`, if it's one they're for.
+    fn sasl_for(&self, host: &str) -> Option<&SaslConfig> {
+        let for_host = self.sasl_hosts.is_empty()
+            || self.sasl_hosts.iter().any(|h| h.eq_ignore_ascii_case(host));
+        self.sasl.as_ref().filter(|_| for_host)
+    }
+}
+
+/// `IRC_SASL_MECH` is `PLAIN` (the default) or `SCRAM-SHA-256`, both of which need
+/// `IRC_SASL_USER` and `IRC_SASL_PASS`, or `EXTERNAL` (needs `IRC_TLS_CERT`).
+/// `IRC_SASL_REQUIRED=1` quits if authentication fails.
+fn sasl_from_env() -> Option<SaslConfig> {
+    let mech = match std::env::var("IRC_SASL_MECH").as_deref() {
+        Ok("EXTERNAL") => Mechanism::External,
+        Ok("SCRAM-SHA-256") => Mechanism::ScramSha256 {
+            user: std::env::var("IRC_SASL_USER").ok()?,
+            pass: std::env::var("IRC_SASL_PASS").ok()?,
+        },
+        Ok("PLAIN") | Err(_) => Mechanism::Plain {
+            user: std::env::var("IRC_SASL_USER").ok()?,
+            pass: std::env::var("IRC_SASL_PASS").ok()?,
+        },
+        Ok(_) => return None,
+    };
+    let required = std::env::var("IRC_SASL_REQUIRED").is_ok_and(|val| val == "1");
+    Some(SaslConfig { mech, required })
+}
+
+fn env_parse<T: std::str::FromStr>(var: &str) -> Option<T> {
+    std::env::var(var).ok().and_then(|val| val.parse().ok())
+}
+
+fn secs_duration(secs: f64) -> Duration {
+    Duration::try_from_secs_f64(secs).unwrap_or_default()
 }
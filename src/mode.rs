@@ -0,0 +1,256 @@
+/// MODE strings split into individual changes
+use std::fmt;
+
+/// How a channel mode takes its argument, after the groups of the ISUPPORT CHANMODES token.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgKind {
+    /// A member status with its NAMES prefix, like `o` and `@`. Takes a nick.
+    Prefix(char),
+    /// Type A: a list like bans. Always takes an argument.
+    List,
+    /// Type B: always takes an argument, like the key.
+    Always,
+    /// Type C: takes an argument only when set, like the limit.
+    WhenSet,
+    /// Type D: a flag.
+    Never,
+}
+
+/// Which channel modes take arguments, from the PREFIX and CHANMODES ISUPPORT tokens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChanModes {
+    /// Mode and NAMES prefix, highest rank first.
+    prefixes: Vec<(char, char)>,
+    list: String,
+    always: String,
+    when_set: String,
+}
+
+impl Default for ChanModes {
+    /// What most servers send: the RFC 2812 set plus the common owner, admin and halfop extensions.
+    fn default() -> Self {
+        Self {
+            prefixes: vec![('q', '~'), ('a', '&'), ('o', '@'), ('h', '%'), ('v', '+')],
+            list: "beI".to_string(),
+            always: "k".to_string(),
+            when_set: "l".to_string(),
+        }
+    }
+}
+
+impl ChanModes {
+    /// Take the modes from a PREFIX value like `(ov)@+`. Returns false if it doesn't parse.
+    pub fn set_prefix(&mut self, value: &str) -> bool {
+        let Some((modes, prefixes)) = value
+            .strip_prefix('(')
+            .and_then(|value| value.split_once(')'))
+        else {
+            return false;
+        };
+        if modes.chars().count() != prefixes.chars().count() {
+            return false;
+        }
+        self.prefixes = modes.chars().zip(prefixes.chars()).collect();
+        true
+    }
+
+    /// Take the groups from a CHANMODES value like `beI,k,l,imnpst`. Returns false if it doesn't
+    /// parse. Type D modes are anything not mentioned elsewhere, so they aren't kept.
+    pub fn set_chanmodes(&mut self, value: &str) -> bool {
+        let groups = value.split(',').collect::<Vec<_>>();
+        // Later groups may be added to the spec, and are to be ignored.
+        if groups.len() < 4 {
+            return false;
+        }
+        self.list = groups[0].to_string();
+        self.always = groups[1].to_string();
+        self.when_set = groups[2].to_string();
+        true
+    }
+
+    pub fn kind(&self, mode: char) -> ArgKind {
+        if let Some(&(_, prefix)) = self.prefixes.iter().find(|(m, _)| *m == mode) {
+            ArgKind::Prefix(prefix)
+        } else if self.list.contains(mode) {
+            ArgKind::List
+        } else if self.always.contains(mode) {
+            ArgKind::Always
+        } else if self.when_set.contains(mode) {
+            ArgKind::WhenSet
+        } else {
+            ArgKind::Never
+        }
+    }
+
+    pub fn is_prefix(&self, c: char) -> bool {
+        self.prefixes.iter().any(|&(_, prefix)| prefix == c)
+    }
+
+    /// NAMES prefixes, highest rank first.
+    pub fn prefixes(&self) -> impl Iterator<Item = char> + '_ {
+        self.prefixes.iter().map(|&(_, prefix)| prefix)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModeChange {
+    /// `+` rather than `-`
+    pub set: bool,
+    pub mode: char,
+    pub kind: ArgKind,
+    pub arg: Option<String>,
+}
+
+impl fmt::Display for ModeChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.set { '+' } else { '-' };
+        match &self.arg {
+            Some(arg) => write!(f, "{sign}{} {arg}", self.mode),
+            None => write!(f, "{sign}{}", self.mode),
+        }
+    }
+}
+
+/// A MODE message, split into changes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mode {
+    Chan {
+        chan: String,
+        changes: Vec<ModeChange>,
+    },
+    User {
+        nick: String,
+        changes: Vec<ModeChange>,
+    },
+}
+
+impl Mode {
+    pub fn chan(chan: &str, modes: &str, args: &[String], chanmodes: &ChanModes) -> Self {
+        Mode::Chan {
+            chan: chan.to_string(),
+            changes: chan_changes(modes, args, chanmodes),
+        }
+    }
+
+    /// User modes are all flags as far as we're concerned.
+    pub fn user(nick: &str, modes: &str) -> Self {
+        Mode::User {
+            nick: nick.to_string(),
+            changes: split(modes, |_, _| (ArgKind::Never, None)),
+        }
+    }
+}
+
+/// Channel modes take their arguments as `chanmodes` says. A change missing its argument is kept
+/// with none, and leftover arguments are dropped.
+pub fn chan_changes(modes: &str, args: &[String], chanmodes: &ChanModes) -> Vec<ModeChange> {
+    let mut args = args.iter();
+    split(modes, |mode, set| {
+        let kind = chanmodes.kind(mode);
+        let takes_arg = match kind {
+            ArgKind::Prefix(_) | ArgKind::List | ArgKind::Always => true,
+            ArgKind::WhenSet => set,
+            ArgKind::Never => false,
+        };
+        let arg = if takes_arg {
+            args.next().cloned()
+        } else {
+            None
+        };
+        (kind, arg)
+    })
+}
+
+/// Walk a mode string like `+ov-b`, asking `arg` for each mode's kind and argument.
+fn split<F>(modes: &str, mut arg: F) -> Vec<ModeChange>
+where
+    F: FnMut(char, bool) -> (ArgKind, Option<String>),
+{
+    let mut set = true;
+    let mut changes = vec![];
+    for mode in modes.chars() {
+        match mode {
+            '+' => set = true,
+            '-' => set = false,
+            _ => {
+                let (kind, arg) = arg(mode, set);
+                changes.push(ModeChange {
+                    set,
+                    mode,
+                    kind,
+                    arg,
+                });
+            }
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn changes(mode: Mode) -> Vec<String> {
+        match mode {
+            Mode::Chan { changes, .. } | Mode::User { changes, .. } => {
+                changes.iter().map(|change| change.to_string()).collect()
+            }
+        }
+    }
+
+    #[test]
+    fn test_chan_modes() {
+        let mode = Mode::chan(
+            "#bobcat",
+            "+ov-b+lk-l",
+            &args(&["nick1", "nick2", "*!*@mask", "10", "key"]),
+            &ChanModes::default(),
+        );
+        assert_eq!(
+            changes(mode),
+            vec![
+                "+o nick1",
+                "+v nick2",
+                "-b *!*@mask",
+                "+l 10",
+                "+k key",
+                "-l"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chan_modes_missing_and_extra_args() {
+        let mode = Mode::chan("#bobcat", "+nt", &args(&["extra"]), &ChanModes::default());
+        assert_eq!(changes(mode), vec!["+n", "+t"]);
+        let mode = Mode::chan("#bobcat", "+o", &[], &ChanModes::default());
+        assert_eq!(changes(mode), vec!["+o"]);
+    }
+
+    #[test]
+    fn test_chan_modes_from_isupport() {
+        let mut chanmodes = ChanModes::default();
+        assert!(chanmodes.set_prefix("(Yov)!@+"));
+        assert!(chanmodes.set_chanmodes("beI,kf,l,imnpst,X"));
+        assert_eq!(chanmodes.kind('Y'), ArgKind::Prefix('!'));
+        assert_eq!(chanmodes.kind('h'), ArgKind::Never);
+        assert_eq!(chanmodes.kind('f'), ArgKind::Always);
+        assert_eq!(chanmodes.kind('X'), ArgKind::Never);
+        assert_eq!(chanmodes.prefixes().collect::<String>(), "!@+");
+
+        assert!(!chanmodes.set_prefix("ov@+"));
+        assert!(!chanmodes.set_prefix("(ov)@"));
+        assert!(!chanmodes.set_chanmodes("beI,k"));
+        assert_eq!(chanmodes.kind('Y'), ArgKind::Prefix('!'));
+    }
+
+    #[test]
+    fn test_user_modes() {
+        let mode = Mode::user("MrNickname", "+wR-ix");
+        assert_eq!(changes(mode), vec!["+w", "+R", "-i", "-x"]);
+    }
+}
@@ -0,0 +1,225 @@
+/// Nick fallbacks during registration and getting the wanted nick back afterwards
+use crate::casemap::CaseMapping;
+use crate::protocol::ClientMsg;
+use std::time::{Duration, Instant};
+
+/// How often to try for the wanted nick while we're on a fallback.
+const REGAIN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Picks nicks for one connection.
+#[derive(Debug)]
+pub struct Nicks {
+    /// The nick we're after: the configured one, or the last one asked for with /nick.
+    wanted: String,
+    /// Left to try if the server turns down the current one, in order.
+    fallbacks: Vec<String>,
+    /// Next attempt at the wanted nick, while we're on a fallback.
+    regain_at: Option<Instant>,
+    /// Whether we asked the server to tell us when the wanted nick frees up.
+    monitoring: bool,
+    casemapping: CaseMapping,
+}
+
+impl Nicks {
+    /// Try `wanted`, then `alts`, then `wanted` with underscores and digits appended.
+    pub fn new(wanted: &str, alts: &[String]) -> Self {
+        let mut fallbacks = alts.to_vec();
+        fallbacks.push(format!("{wanted}_"));
+        fallbacks.push(format!("{wanted}__"));
+        fallbacks.extend((1..=9).map(|n| format!("{wanted}{n}")));
+        fallbacks.retain(|nick| nick != wanted);
+        fallbacks.reverse();
+        Self {
+            wanted: wanted.to_string(),
+            fallbacks,
+            regain_at: None,
+            monitoring: false,
+            casemapping: CaseMapping::default(),
+        }
+    }
+
+    pub fn wanted(&self) -> &str {
+        &self.wanted
+    }
+
+    pub fn set_casemapping(&mut self, casemapping: CaseMapping) {
+        self.casemapping = casemapping;
+    }
+
+    fn is_wanted(&self, nick: &str) -> bool {
+        self.casemapping.eq(nick, &self.wanted)
+    }
+
+    /// The server turned down our nick during registration. `None` once out of ideas.
+    pub fn next(&mut self) -> Option<String> {
+        self.fallbacks.pop()
+    }
+
+    /// Registered as `nick`. If that's a fallback, retry the wanted one now and then.
+    pub fn registered(&mut self, nick: &str, now: Instant) {
+        if !self.is_wanted(nick) {
+            self.regain_at = Some(now + REGAIN_INTERVAL);
+        }
+    }
+
+    /// The server supports MONITOR. Have it tell us when the wanted nick frees up, if we're
+    /// waiting for it.
+    pub fn monitor(&mut self) -> Option<ClientMsg> {
+        if self.regain_at.is_none() || self.monitoring {
+            return None;
+        }
+        self.monitoring = true;
+        Some(ClientMsg::MonitorAdd {
+            nick: self.wanted.clone(),
+        })
+    }
+
+    /// Our nick is now `nick`.
+    pub fn changed(&mut self, nick: &str) -> Option<ClientMsg> {
+        if !self.is_wanted(nick) {
+            return None;
+        }
+        self.stop_regain()
+    }
+
+    /// Asked for a nick with /nick. It's up to the user from here, so stop trying for the old one.
+    pub fn want(&mut self, nick: &str) -> Option<ClientMsg> {
+        let msg = self.stop_regain();
+        self.wanted = nick.to_string();
+        msg
+    }
+
+    fn stop_regain(&mut self) -> Option<ClientMsg> {
+        self.regain_at = None;
+        std::mem::take(&mut self.monitoring).then(|| ClientMsg::MonitorRemove {
+            nick: self.wanted.clone(),
+        })
+    }
+
+    /// Whether a nick error is about one of our periodic attempts, which nobody needs to hear
+    /// about.
+    pub fn is_regaining(&self, nick: &str) -> bool {
+        self.regain_at.is_some() && self.is_wanted(nick)
+    }
+
+    /// When `tick` will have something to do.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.regain_at
+    }
+
+    pub fn tick(&mut self, now: Instant) -> Option<ClientMsg> {
+        let regain_at = self.regain_at.as_mut()?;
+        if now < *regain_at {
+            return None;
+        }
+        *regain_at = now + REGAIN_INTERVAL;
+        Some(self.regain())
+    }
+
+    /// MONITOR says these nicks went away. Go for the wanted one if it's among them.
+    pub fn offline(&mut self, targets: &[String]) -> Option<ClientMsg> {
+        self.regain_at?;
+        targets
+            .iter()
+            .any(|nick| self.is_wanted(nick))
+            .then(|| self.regain())
+    }
+
+    fn regain(&self) -> ClientMsg {
+        ClientMsg::Nick {
+            nick: self.wanted.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nick(nick: &str) -> ClientMsg {
+        ClientMsg::Nick {
+            nick: nick.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_fallbacks() {
+        let mut nicks = Nicks::new("bob", &["robert".to_string(), "bob".to_string()]);
+        assert_eq!(nicks.next().as_deref(), Some("robert"));
+        assert_eq!(nicks.next().as_deref(), Some("bob_"));
+        assert_eq!(nicks.next().as_deref(), Some("bob__"));
+        for n in 1..=9 {
+            assert_eq!(nicks.next(), Some(format!("bob{n}")));
+        }
+        assert_eq!(nicks.next(), None);
+    }
+
+    #[test]
+    fn test_registered_as_wanted() {
+        let now = Instant::now();
+        let mut nicks = Nicks::new("bob", &[]);
+        nicks.registered("bob", now);
+        assert_eq!(nicks.deadline(), None);
+        assert_eq!(nicks.monitor(), None);
+        assert_eq!(nicks.offline(&["bob".to_string()]), None);
+    }
+
+    #[test]
+    fn test_regain() {
+        let now = Instant::now();
+        let mut nicks = Nicks::new("bob", &[]);
+        nicks.registered("bob_", now);
+        assert_eq!(
+            nicks.monitor(),
+            Some(ClientMsg::MonitorAdd {
+                nick: "bob".to_string()
+            })
+        );
+        assert_eq!(nicks.monitor(), None);
+        assert!(nicks.is_regaining("bob"));
+        assert_eq!(nicks.tick(now), None);
+        let later = now + REGAIN_INTERVAL;
+        assert_eq!(nicks.deadline(), Some(later));
+        assert_eq!(nicks.tick(later), Some(nick("bob")));
+        assert_eq!(nicks.deadline(), Some(later + REGAIN_INTERVAL));
+
+        assert_eq!(nicks.offline(&["alice".to_string()]), None);
+        assert_eq!(nicks.offline(&["BOB".to_string()]), Some(nick("bob")));
+
+        assert_eq!(
+            nicks.changed("Bob"),
+            Some(ClientMsg::MonitorRemove {
+                nick: "bob".to_string()
+            })
+        );
+        assert!(!nicks.is_regaining("bob"));
+        assert_eq!(nicks.deadline(), None);
+    }
+
+    #[test]
+    fn test_want_stops_regain() {
+        let now = Instant::now();
+        let mut nicks = Nicks::new("bob", &[]);
+        nicks.registered("bob_", now);
+        nicks.monitor();
+        assert_eq!(
+            nicks.want("robert"),
+            Some(ClientMsg::MonitorRemove {
+                nick: "bob".to_string()
+            })
+        );
+        assert_eq!(nicks.wanted(), "robert");
+        assert_eq!(nicks.deadline(), None);
+        assert_eq!(nicks.changed("robert"), None);
+    }
+
+    #[test]
+    fn test_regain_without_monitor() {
+        let now = Instant::now();
+        let mut nicks = Nicks::new("bob", &[]);
+        nicks.registered("bob_", now);
+        assert_eq!(nicks.tick(now + REGAIN_INTERVAL), Some(nick("bob")));
+        assert_eq!(nicks.changed("bob"), None);
+        assert_eq!(nicks.deadline(), None);
+    }
+}
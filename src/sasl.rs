@@ -0,0 +1,475 @@
+/// SASL authentication over AUTHENTICATE
+///
+/// See https://ircv3.net/specs/extensions/sasl-3.1
+use crate::protocol::{ClientMsg, ServCmd};
+use crate::scram::Scram;
+use base64::prelude::*;
+use std::fmt;
+
+/// AUTHENTICATE payloads are sent base64-encoded in chunks of at most this many bytes.
+const CHUNK_LEN: usize = 400;
+
+#[derive(Clone, PartialEq)]
+pub enum Mechanism {
+    Plain {
+        user: String,
+        pass: String,
+    },
+    /// Authenticate with the TLS client certificate.
+    External,
+    ScramSha256 {
+        user: String,
+        pass: String,
+    },
+}
+
+/// Leaves the password out, so configs can be logged.
+impl fmt::Debug for Mechanism {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Plain { user, .. } => f
+                .debug_struct("Plain")
+                .field("user", user)
+                .field("pass", &"<redacted>")
+                .finish(),
+            Self::External => f.write_str("External"),
+            Self::ScramSha256 { user, .. } => f
+                .debug_struct("ScramSha256")
+                .field("user", user)
+                .field("pass", &"<redacted>")
+                .finish(),
+        }
+    }
+}
+
+impl Mechanism {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Plain { .. } => "PLAIN",
+            Self::External => "EXTERNAL",
+            Self::ScramSha256 { .. } => "SCRAM-SHA-256",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SaslConfig {
+    pub mech: Mechanism,
+    /// Quit instead of registering unauthenticated when authentication fails.
+    pub required: bool,
+}
+
+impl SaslConfig {
+    /// Why the credentials shouldn't go over a connection, if they shouldn't. Passwords need
+    /// TLS, and a verified certificate unless `allow_unverified`.
+    pub fn refusal(&self, tls: bool, verify: bool, allow_unverified: bool) -> Option<&'static str> {
+        match self.mech {
+            Mechanism::External => None,
+            _ if !tls => Some("the connection isn't encrypted"),
+            _ if !verify && !allow_unverified => Some("the server certificate isn't verified"),
+            _ => None,
+        }
+    }
+}
+
+/// Where an exchange stands after a server message.
+#[derive(Debug, PartialEq)]
+pub enum Progress {
+    /// Send these messages and keep waiting.
+    Continue(Vec<ClientMsg>),
+    Success,
+    Failure(String),
+}
+
+/// One authentication exchange.
+#[derive(Debug)]
+pub struct Authenticator {
+    config: SaslConfig,
+    /// Sent `AUTHENTICATE <mechanism>` and waiting for challenges.
+    started: bool,
+    /// Base64 of a challenge split over several AUTHENTICATE lines.
+    challenge: String,
+    /// Present for multi-step mechanisms.
+    scram: Option<Scram>,
+    /// Why we aborted the exchange, reported once the server confirms with 906.
+    aborted: Option<String>,
+}
+
+impl Authenticator {
+    pub fn new(config: SaslConfig) -> Self {
+        let scram = match &config.mech {
+            Mechanism::ScramSha256 { user, pass } => Some(Scram::new(user, pass)),
+            _ => None,
+        };
+        Self {
+            config,
+            started: false,
+            challenge: String::new(),
+            scram,
+            aborted: None,
+        }
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.config.required
+    }
+
+    /// Begin authenticating. `offered` is the value of the `sasl` capability, if the server
+    /// advertised one, listing the mechanisms it accepts.
+    pub fn start(&mut self, offered: Option<&str>) -> Progress {
+        let name = self.config.mech.name();
+        if let Some(offered) = offered {
+            if !offered
+                .split(',')
+                .any(|mech| mech.eq_ignore_ascii_case(name))
+            {
+                return Progress::Failure(format!("server does not offer SASL {name}"));
+            }
+        }
+        self.started = true;
+        Progress::Continue(vec![ClientMsg::Authenticate {
+            data: name.to_string(),
+        }])
+    }
+
+    pub fn handle(&mut self, cmd: &ServCmd) -> Option<Progress> {
+        let progress = match cmd {
+            ServCmd::Authenticate { data } if self.started => {
+                // A full 400 byte chunk means more of the same challenge follows.
+                if data != "+" {
+                    self.challenge.push_str(data);
+                    if data.len() == CHUNK_LEN {
+                        return Some(Progress::Continue(vec![]));
+                    }
+                }
+                let challenge = std::mem::take(&mut self.challenge);
+                match BASE64_STANDARD.decode(challenge) {
+                    Ok(challenge) => self.respond(&challenge),
+                    Err(_) => self.abort("challenge is not valid base64".to_string()),
+                }
+            }
+            // SCRAM authenticates the server too, so don't take its word for it.
+            ServCmd::RplSaslSuccess { .. } | ServCmd::ErrSaslAlready { .. } => match &self.scram {
+                Some(scram) if !scram.is_verified() => {
+                    Progress::Failure("server did not prove it knows the password".to_string())
+                }
+                _ => Progress::Success,
+            },
+            ServCmd::ErrSaslAborted { msg } => {
+                Progress::Failure(self.aborted.take().unwrap_or_else(|| msg.clone()))
+            }
+            ServCmd::ErrNickLocked { msg }
+            | ServCmd::ErrSaslFail { msg }
+            | ServCmd::ErrSaslTooLong { msg } => Progress::Failure(msg.clone()),
+            _ => return None,
+        };
+        Some(progress)
+    }
+
+    fn respond(&mut self, challenge: &[u8]) -> Progress {
+        let response = match &self.config.mech {
+            Mechanism::Plain { user, pass } => Ok(format!("{user}\0{user}\0{pass}").into_bytes()),
+            // The server takes the identity from the certificate.
+            Mechanism::External => Ok(vec![]),
+            Mechanism::ScramSha256 { .. } => self
+                .scram
+                .as_mut()
+                .expect("SCRAM state for SCRAM mechanism")
+                .respond(challenge),
+        };
+        match response {
+            Ok(response) => Progress::Continue(authenticate(&response)),
+            Err(reason) => self.abort(reason),
+        }
+    }
+
+    fn abort(&mut self, reason: String) -> Progress {
+        self.aborted = Some(reason);
+        Progress::Continue(vec![ClientMsg::Authenticate {
+            data: "*".to_string(),
+        }])
+    }
+}
+
+/// Encode a client response as AUTHENTICATE lines: 400-byte chunks of base64, then a `+` if the
+/// last chunk was exactly 400 bytes long (or the response is empty).
+pub fn authenticate(payload: &[u8]) -> Vec<ClientMsg> {
+    let encoded = BASE64_STANDARD.encode(payload);
+    let mut msgs = encoded
+        .as_bytes()
+        .chunks(CHUNK_LEN)
+        .map(|chunk| ClientMsg::Authenticate {
+            data: String::from_utf8_lossy(chunk).to_string(),
+        })
+        .collect::<Vec<_>>();
+    if encoded.len().is_multiple_of(CHUNK_LEN) {
+        msgs.push(ClientMsg::Authenticate {
+            data: "+".to_string(),
+        });
+    }
+    msgs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::parse_msg;
+
+    fn plain(required: bool) -> Authenticator {
+        Authenticator::new(SaslConfig {
+            mech: Mechanism::Plain {
+                user: "jilles".to_string(),
+                pass: "sesame".to_string(),
+            },
+            required,
+        })
+    }
+
+    fn msg(data: &str) -> ClientMsg {
+        ClientMsg::Authenticate {
+            data: data.to_string(),
+        }
+    }
+
+    fn handle(auth: &mut Authenticator, line: &str) -> Option<Progress> {
+        auth.handle(&parse_msg(line).unwrap().command)
+    }
+
+    #[test]
+    fn test_refusal() {
+        let plain = plain(false).config;
+        assert_eq!(plain.refusal(true, true, false), None);
+        assert_eq!(
+            plain.refusal(false, true, true),
+            Some("the connection isn't encrypted")
+        );
+        assert_eq!(
+            plain.refusal(true, false, false),
+            Some("the server certificate isn't verified")
+        );
+        assert_eq!(plain.refusal(true, false, true), None);
+        let external = SaslConfig {
+            mech: Mechanism::External,
+            required: true,
+        };
+        assert_eq!(external.refusal(true, false, false), None);
+    }
+
+    #[test]
+    fn test_plain() {
+        let mut auth = plain(false);
+        assert_eq!(
+            auth.start(Some("PLAIN,EXTERNAL")),
+            Progress::Continue(vec![msg("PLAIN")])
+        );
+        assert_eq!(
+            handle(&mut auth, "AUTHENTICATE +"),
+            Some(Progress::Continue(vec![msg(
+                "amlsbGVzAGppbGxlcwBzZXNhbWU="
+            )]))
+        );
+        assert_eq!(
+            handle(
+                &mut auth,
+                ":irc.example.com 900 jilles jilles!jilles@localhost jilles :You are now logged in as jilles"
+            ),
+            None
+        );
+        assert_eq!(
+            handle(
+                &mut auth,
+                ":irc.example.com 903 jilles :SASL authentication successful"
+            ),
+            Some(Progress::Success)
+        );
+    }
+
+    #[test]
+    fn test_external() {
+        let mut auth = Authenticator::new(SaslConfig {
+            mech: Mechanism::External,
+            required: true,
+        });
+        assert_eq!(auth.start(None), Progress::Continue(vec![msg("EXTERNAL")]));
+        assert_eq!(
+            handle(&mut auth, "AUTHENTICATE +"),
+            Some(Progress::Continue(vec![msg("+")]))
+        );
+        assert_eq!(
+            handle(
+                &mut auth,
+                ":irc.example.com 904 MrNickname :SASL authentication failed"
+            ),
+            Some(Progress::Failure("SASL authentication failed".to_string()))
+        );
+        assert!(auth.is_required());
+    }
+
+    #[test]
+    fn test_mechanism_not_offered() {
+        let mut auth = plain(false);
+        assert_eq!(
+            auth.start(Some("EXTERNAL,SCRAM-SHA-256")),
+            Progress::Failure("server does not offer SASL PLAIN".to_string())
+        );
+    }
+
+    #[test]
+    fn test_already_authenticated() {
+        let mut auth = plain(false);
+        auth.start(None);
+        assert_eq!(
+            handle(
+                &mut auth,
+                ":irc.example.com 907 MrNickname :You have already authenticated using SASL"
+            ),
+            Some(Progress::Success)
+        );
+    }
+
+    #[test]
+    fn test_scram_sha_256() {
+        let mut auth = Authenticator::new(SaslConfig {
+            mech: Mechanism::ScramSha256 {
+                user: "user".to_string(),
+                pass: "pencil".to_string(),
+            },
+            required: true,
+        });
+        auth.scram = Some(Scram::with_nonce("user", "pencil", "rOprNGfwEbeRWgbNEkqO"));
+        assert_eq!(
+            auth.start(Some("SCRAM-SHA-256,PLAIN")),
+            Progress::Continue(vec![msg("SCRAM-SHA-256")])
+        );
+
+        let encode = |text: &str| msg(&BASE64_STANDARD.encode(text));
+        assert_eq!(
+            handle(&mut auth, "AUTHENTICATE +"),
+            Some(Progress::Continue(vec![encode(
+                "n,,n=user,r=rOprNGfwEbeRWgbNEkqO"
+            )]))
+        );
+        let server_first = BASE64_STANDARD.encode(
+            "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096",
+        );
+        assert_eq!(
+            handle(&mut auth, &format!("AUTHENTICATE {server_first}")),
+            Some(Progress::Continue(vec![encode(
+                "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+            )]))
+        );
+        let server_final = BASE64_STANDARD.encode("v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=");
+        assert_eq!(
+            handle(&mut auth, &format!("AUTHENTICATE {server_final}")),
+            Some(Progress::Continue(vec![msg("+")]))
+        );
+        assert_eq!(
+            handle(
+                &mut auth,
+                ":irc.example.com 903 user :SASL authentication successful"
+            ),
+            Some(Progress::Success)
+        );
+    }
+
+    #[test]
+    fn test_scram_success_without_server_final() {
+        let mut auth = Authenticator::new(SaslConfig {
+            mech: Mechanism::ScramSha256 {
+                user: "user".to_string(),
+                pass: "pencil".to_string(),
+            },
+            required: true,
+        });
+        auth.scram = Some(Scram::with_nonce("user", "pencil", "rOprNGfwEbeRWgbNEkqO"));
+        auth.start(None);
+        handle(&mut auth, "AUTHENTICATE +");
+        let server_first = BASE64_STANDARD.encode(
+            "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096",
+        );
+        handle(&mut auth, &format!("AUTHENTICATE {server_first}"));
+        // 903 straight after client-final, skipping server-final
+        assert_eq!(
+            handle(
+                &mut auth,
+                ":irc.example.com 903 user :SASL authentication successful"
+            ),
+            Some(Progress::Failure(
+                "server did not prove it knows the password".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_scram_bad_signature_aborts() {
+        let mut auth = Authenticator::new(SaslConfig {
+            mech: Mechanism::ScramSha256 {
+                user: "user".to_string(),
+                pass: "pencil".to_string(),
+            },
+            required: true,
+        });
+        auth.scram = Some(Scram::with_nonce("user", "pencil", "rOprNGfwEbeRWgbNEkqO"));
+        auth.start(None);
+        handle(&mut auth, "AUTHENTICATE +");
+        let server_first = BASE64_STANDARD.encode(
+            "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096",
+        );
+        handle(&mut auth, &format!("AUTHENTICATE {server_first}"));
+        let forged = BASE64_STANDARD.encode("v=AAAATRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=");
+        assert_eq!(
+            handle(&mut auth, &format!("AUTHENTICATE {forged}")),
+            Some(Progress::Continue(vec![msg("*")]))
+        );
+        assert_eq!(
+            handle(
+                &mut auth,
+                ":irc.example.com 906 user :SASL authentication aborted"
+            ),
+            Some(Progress::Failure("server signature mismatch".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_challenge_chunks() {
+        let mut auth = plain(false);
+        auth.start(None);
+        // A 400 byte chunk followed by the rest of the challenge.
+        let chunk = "A".repeat(400);
+        assert_eq!(
+            handle(&mut auth, &format!("AUTHENTICATE {chunk}")),
+            Some(Progress::Continue(vec![]))
+        );
+        assert_eq!(auth.challenge.len(), 400);
+        assert!(matches!(
+            handle(&mut auth, "AUTHENTICATE AAAA"),
+            Some(Progress::Continue(lines)) if !lines.is_empty()
+        ));
+        assert!(auth.challenge.is_empty());
+
+        // A challenge of exactly 400 bytes is terminated by a lone +.
+        let mut auth = plain(false);
+        auth.start(None);
+        handle(&mut auth, &format!("AUTHENTICATE {chunk}"));
+        assert!(matches!(
+            handle(&mut auth, "AUTHENTICATE +"),
+            Some(Progress::Continue(lines)) if !lines.is_empty()
+        ));
+    }
+
+    #[test]
+    fn test_authenticate_chunks() {
+        // 300 bytes encode to exactly 400 base64 bytes, so an empty chunk must follow.
+        let lines = authenticate(&[0; 300]);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], msg(&"A".repeat(400)));
+        assert_eq!(lines[1], msg("+"));
+
+        let lines = authenticate(&[0; 301]);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1], msg("AA=="));
+
+        assert_eq!(authenticate(b""), vec![msg("+")]);
+    }
+}
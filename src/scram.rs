@@ -0,0 +1,277 @@
+/// SCRAM-SHA-256 client (RFC 5802, RFC 7677)
+use base64::prelude::*;
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Refuse to spend more than this many PBKDF2 rounds on the UI thread. Servers use 4096 to a
+/// few tens of thousands; this many takes tens of milliseconds in a release build.
+const MAX_ITERATIONS: u32 = 100_000;
+
+#[derive(Debug)]
+enum Step {
+    /// Waiting for the empty challenge before sending client-first.
+    Initial,
+    /// Sent client-first, waiting for server-first.
+    ClientFirst { client_first_bare: String },
+    /// Sent client-final, waiting for the server signature.
+    ClientFinal { server_signature: Vec<u8> },
+    /// The server proved it knows the password.
+    Verified,
+    /// The exchange went wrong.
+    Failed,
+}
+
+pub struct Scram {
+    user: String,
+    pass: String,
+    nonce: String,
+    step: Step,
+}
+
+/// Leaves the password out.
+impl fmt::Debug for Scram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Scram")
+            .field("user", &self.user)
+            .field("pass", &"<redacted>")
+            .field("nonce", &self.nonce)
+            .field("step", &self.step)
+            .finish()
+    }
+}
+
+impl Scram {
+    pub fn new(user: &str, pass: &str) -> Self {
+        let nonce = BASE64_STANDARD.encode(rand::random::<[u8; 18]>());
+        Self::with_nonce(user, pass, &nonce)
+    }
+
+    pub fn with_nonce(user: &str, pass: &str, nonce: &str) -> Self {
+        Self {
+            user: user.to_string(),
+            pass: pass.to_string(),
+            nonce: nonce.to_string(),
+            step: Step::Initial,
+        }
+    }
+
+    /// Answer the next server challenge. The final, empty answer is only given after the
+    /// server signature checks out.
+    pub fn respond(&mut self, challenge: &[u8]) -> Result<Vec<u8>, String> {
+        let step = std::mem::replace(&mut self.step, Step::Failed);
+        match step {
+            Step::Initial => {
+                let client_first_bare = format!("n={},r={}", escape(&self.user), self.nonce);
+                let msg = format!("n,,{client_first_bare}");
+                self.step = Step::ClientFirst { client_first_bare };
+                Ok(msg.into_bytes())
+            }
+            Step::ClientFirst { client_first_bare } => {
+                let server_first = std::str::from_utf8(challenge)
+                    .map_err(|_| "server-first message is not UTF-8".to_string())?;
+                let (msg, server_signature) =
+                    self.client_final(&client_first_bare, server_first)?;
+                self.step = Step::ClientFinal { server_signature };
+                Ok(msg.into_bytes())
+            }
+            Step::ClientFinal { server_signature } => {
+                let server_final = std::str::from_utf8(challenge)
+                    .map_err(|_| "server-final message is not UTF-8".to_string())?;
+                if let Some(err) = attr(server_final, 'e') {
+                    return Err(format!("server error: {err}"));
+                }
+                let verifier = attr(server_final, 'v')
+                    .and_then(|v| BASE64_STANDARD.decode(v).ok())
+                    .ok_or("malformed server-final message")?;
+                if verifier != server_signature {
+                    return Err("server signature mismatch".to_string());
+                }
+                self.step = Step::Verified;
+                Ok(vec![])
+            }
+            Step::Verified | Step::Failed => {
+                Err("unexpected challenge after authentication".to_string())
+            }
+        }
+    }
+
+    /// Whether the server proved it knows the password.
+    pub fn is_verified(&self) -> bool {
+        matches!(self.step, Step::Verified)
+    }
+
+    /// Build client-final from server-first, and the server signature to expect in return.
+    fn client_final(
+        &self,
+        client_first_bare: &str,
+        server_first: &str,
+    ) -> Result<(String, Vec<u8>), String> {
+        if attr(server_first, 'm').is_some() {
+            return Err("unsupported mandatory extension".to_string());
+        }
+        let nonce = attr(server_first, 'r').ok_or("server-first has no nonce")?;
+        if !nonce.starts_with(&self.nonce) || nonce.len() == self.nonce.len() {
+            return Err("server nonce does not extend ours".to_string());
+        }
+        let salt = attr(server_first, 's')
+            .and_then(|s| BASE64_STANDARD.decode(s).ok())
+            .ok_or("server-first has no valid salt")?;
+        let iterations = attr(server_first, 'i')
+            .and_then(|i| i.parse::<u32>().ok())
+            .filter(|i| (1..=MAX_ITERATIONS).contains(i))
+            .ok_or("server-first has no valid iteration count")?;
+
+        // "biws" is base64 of the "n,," GS2 header: no channel binding, no authzid.
+        let without_proof = format!("c=biws,r={nonce}");
+        let auth_message = format!("{client_first_bare},{server_first},{without_proof}");
+
+        let salted = hi(self.pass.as_bytes(), &salt, iterations);
+        let client_key = hmac(&salted, b"Client Key");
+        let stored_key = Sha256::digest(&client_key);
+        let client_signature = hmac(&stored_key, auth_message.as_bytes());
+        let proof = client_key
+            .iter()
+            .zip(&client_signature)
+            .map(|(k, s)| k ^ s)
+            .collect::<Vec<_>>();
+        let server_key = hmac(&salted, b"Server Key");
+        let server_signature = hmac(&server_key, auth_message.as_bytes());
+
+        let msg = format!("{without_proof},p={}", BASE64_STANDARD.encode(proof));
+        Ok((msg, server_signature))
+    }
+}
+
+/// Value of the `<key>=` attribute in a comma separated SCRAM message.
+fn attr(msg: &str, key: char) -> Option<&str> {
+    msg.split(',').find_map(|field| {
+        let mut chars = field.chars();
+        (chars.next() == Some(key) && chars.next() == Some('=')).then(|| &field[2..])
+    })
+}
+
+/// Usernames can't carry raw `,` or `=`.
+fn escape(user: &str) -> String {
+    user.replace('=', "=3D").replace(',', "=2C")
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// PBKDF2-HMAC-SHA-256 with a single output block, which is all SCRAM-SHA-256 needs.
+fn hi(pass: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+    let mut u = hmac(pass, &[salt, &1u32.to_be_bytes()].concat());
+    let mut out = u.clone();
+    for _ in 1..iterations {
+        u = hmac(pass, &u);
+        out.iter_mut().zip(&u).for_each(|(o, u)| *o ^= u);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 7677 section 3.
+    const NONCE: &str = "rOprNGfwEbeRWgbNEkqO";
+    const SERVER_FIRST: &str = "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+        s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+    const CLIENT_FINAL: &str = "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+        p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=";
+    const SERVER_FINAL: &str = "v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=";
+
+    fn respond(scram: &mut Scram, challenge: &str) -> Result<String, String> {
+        scram
+            .respond(challenge.as_bytes())
+            .map(|msg| String::from_utf8(msg).unwrap())
+    }
+
+    #[test]
+    fn test_debug_hides_password() {
+        let debug = format!("{:?}", Scram::with_nonce("user", "pencil", NONCE));
+        assert!(debug.contains("user"));
+        assert!(!debug.contains("pencil"));
+    }
+
+    #[test]
+    fn test_rfc7677_exchange() {
+        let mut scram = Scram::with_nonce("user", "pencil", NONCE);
+        assert_eq!(
+            respond(&mut scram, ""),
+            Ok("n,,n=user,r=rOprNGfwEbeRWgbNEkqO".to_string())
+        );
+        assert_eq!(
+            respond(&mut scram, SERVER_FIRST),
+            Ok(CLIENT_FINAL.to_string())
+        );
+        assert_eq!(respond(&mut scram, SERVER_FINAL), Ok("".to_string()));
+    }
+
+    #[test]
+    fn test_bad_server_signature() {
+        let mut scram = Scram::with_nonce("user", "pencil", NONCE);
+        respond(&mut scram, "").unwrap();
+        respond(&mut scram, SERVER_FIRST).unwrap();
+        assert_eq!(
+            respond(&mut scram, "v=AAAATRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4="),
+            Err("server signature mismatch".to_string())
+        );
+    }
+
+    #[test]
+    fn test_wrong_password_changes_proof() {
+        let mut scram = Scram::with_nonce("user", "pencil2", NONCE);
+        respond(&mut scram, "").unwrap();
+        assert_ne!(
+            respond(&mut scram, SERVER_FIRST),
+            Ok(CLIENT_FINAL.to_string())
+        );
+        assert!(respond(&mut scram, SERVER_FINAL).is_err());
+    }
+
+    #[test]
+    fn test_nonce_must_extend_ours() {
+        let mut scram = Scram::with_nonce("user", "pencil", NONCE);
+        respond(&mut scram, "").unwrap();
+        assert_eq!(
+            respond(
+                &mut scram,
+                "r=somebodyElsesNonce,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096"
+            ),
+            Err("server nonce does not extend ours".to_string())
+        );
+    }
+
+    #[test]
+    fn test_server_error() {
+        let mut scram = Scram::with_nonce("user", "pencil", NONCE);
+        respond(&mut scram, "").unwrap();
+        respond(&mut scram, SERVER_FIRST).unwrap();
+        assert_eq!(
+            respond(&mut scram, "e=invalid-proof"),
+            Err("server error: invalid-proof".to_string())
+        );
+    }
+
+    #[test]
+    fn test_escape_user() {
+        let mut scram = Scram::with_nonce("a=b,c", "pencil", NONCE);
+        assert_eq!(
+            respond(&mut scram, ""),
+            Ok("n,,n=a=3Db=2Cc,r=rOprNGfwEbeRWgbNEkqO".to_string())
+        );
+    }
+
+    #[test]
+    fn test_random_nonce() {
+        let a = Scram::new("user", "pencil");
+        let b = Scram::new("user", "pencil");
+        assert_eq!(a.nonce.len(), 24);
+        assert_ne!(a.nonce, b.nonce);
+    }
+}
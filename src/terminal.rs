@@ -1,14 +1,15 @@
+use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
 use crossterm::execute;
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
 };
 use std::io;
 
-/// Enable raw mode and push a panic hook that restores the terminal.
+/// Enable raw mode and mouse events, and push a panic hook that restores the terminal.
 pub fn setup() -> io::Result<()> {
     set_panic_hook();
     enable_raw_mode()?;
-    execute!(io::stdout(), EnterAlternateScreen)?;
+    execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture)?;
     Ok(())
 }
 
@@ -24,6 +25,6 @@ fn set_panic_hook() {
 /// Get out of raw mode and switch back to the main screen.
 pub fn restore() -> io::Result<()> {
     disable_raw_mode()?;
-    execute!(io::stdout(), LeaveAlternateScreen)?;
+    execute!(io::stdout(), DisableMouseCapture, LeaveAlternateScreen)?;
     Ok(())
 }
@@ -0,0 +1,256 @@
+/// TLS connection setup
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{ring, verify_tls12_signature, verify_tls13_signature};
+use tokio_rustls::rustls::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use tokio_rustls::TlsConnector;
+
+/// Security of an established connection, as reported to the server tab.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TlsState {
+    Plain,
+    Tls {
+        version: String,
+        cipher: String,
+        verified: bool,
+    },
+}
+
+impl fmt::Display for TlsState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Plain => write!(f, "plaintext"),
+            Self::Tls {
+                version,
+                cipher,
+                verified,
+            } => {
+                write!(f, "{version}, {cipher}")?;
+                if !verified {
+                    write!(f, ", certificate NOT verified")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Perform a TLS handshake over an already connected socket. With `verify` the server
+/// certificate is checked against the system roots; without it any certificate is accepted,
+/// which is only meant for self-signed test servers. `client_cert` is a PEM file holding the
+/// certificate chain and private key to present to the server, e.g. for SASL EXTERNAL.
+pub async fn handshake(
+    stream: TcpStream,
+    host: &str,
+    verify: bool,
+    client_cert: Option<&Path>,
+) -> io::Result<(TlsStream<TcpStream>, TlsState)> {
+    let connector = TlsConnector::from(Arc::new(client_config(verify, client_cert)?));
+    let server_name = ServerName::try_from(host.to_string())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let stream = connector.connect(server_name, stream).await?;
+
+    let (_, conn) = stream.get_ref();
+    let state = TlsState::Tls {
+        version: conn
+            .protocol_version()
+            .map(|v| format!("{v:?}"))
+            .unwrap_or_default(),
+        cipher: conn
+            .negotiated_cipher_suite()
+            .map(|c| format!("{:?}", c.suite()))
+            .unwrap_or_default(),
+        verified: verify,
+    };
+    Ok((stream, state))
+}
+
+fn client_config(verify: bool, client_cert: Option<&Path>) -> io::Result<ClientConfig> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?;
+
+    let builder = if verify {
+        builder.with_root_certificates(system_roots()?)
+    } else {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerification(provider)))
+    };
+    match client_cert {
+        Some(path) => {
+            let (certs, key) = load_client_cert(path)?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .map_err(io::Error::other)
+        }
+        None => Ok(builder.with_no_client_auth()),
+    }
+}
+
+fn load_client_cert(
+    path: &Path,
+) -> io::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, format!("{path:?}: {e}"));
+    let certs = CertificateDer::pem_file_iter(path)
+        .map_err(invalid)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid)?;
+    let key = PrivateKeyDer::from_pem_file(path).map_err(invalid)?;
+    Ok((certs, key))
+}
+
+fn system_roots() -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    let native = rustls_native_certs::load_native_certs();
+    let (added, _) = roots.add_parsable_certificates(native.certs);
+    if added == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no usable system root certificates found",
+        ));
+    }
+    Ok(roots)
+}
+
+/// Accepts any server certificate, but still checks handshake signatures so that the
+/// session is at least bound to the key the server presented.
+#[derive(Debug)]
+struct NoVerification(Arc<CryptoProvider>);
+
+impl NoVerification {
+    fn algorithms(&self) -> &WebPkiSupportedAlgorithms {
+        &self.0.signature_verification_algorithms
+    }
+}
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        verify_tls12_signature(message, cert, dss, self.algorithms())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        verify_tls13_signature(message, cert, dss, self.algorithms())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms().supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio_rustls::rustls::pki_types::PrivateKeyDer;
+    use tokio_rustls::rustls::ServerConfig;
+    use tokio_rustls::TlsAcceptor;
+
+    /// A one-shot TLS server with a freshly generated self-signed certificate for `localhost`.
+    /// Sends a welcome line and echoes back the first line it reads.
+    async fn tls_stand_in() -> (u16, tokio::task::JoinHandle<Option<String>>) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = PrivateKeyDer::try_from(cert.signing_key.serialize_der()).unwrap();
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.cert.der().clone()], key)
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (sock, _) = listener.accept().await.unwrap();
+            let stream = acceptor.accept(sock).await.ok()?;
+            let (reader, mut writer) = tokio::io::split(stream);
+            writer
+                .write_all(b":localhost NOTICE * :hello\r\n")
+                .await
+                .unwrap();
+            BufReader::new(reader).lines().next_line().await.ok()?
+        });
+        (port, server)
+    }
+
+    #[tokio::test]
+    async fn test_handshake_self_signed_unverified() {
+        let (port, server) = tls_stand_in().await;
+        let sock = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+
+        let (stream, state) = handshake(sock, "localhost", false, None).await.unwrap();
+        assert!(matches!(
+            state,
+            TlsState::Tls {
+                verified: false,
+                ..
+            }
+        ));
+
+        let (reader, mut writer) = tokio::io::split(stream);
+        let line = BufReader::new(reader).lines().next_line().await.unwrap();
+        assert_eq!(line, Some(":localhost NOTICE * :hello".to_string()));
+        writer.write_all(b"NICK MrNickname\r\n").await.unwrap();
+        writer.flush().await.unwrap();
+
+        assert_eq!(server.await.unwrap(), Some("NICK MrNickname".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_self_signed_verified_fails() {
+        let (port, server) = tls_stand_in().await;
+        let sock = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+
+        // Either the system has no roots at all or the self-signed certificate is rejected.
+        assert!(handshake(sock, "localhost", true, None).await.is_err());
+        assert_eq!(server.await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_display_tls_state() {
+        assert_eq!(TlsState::Plain.to_string(), "plaintext");
+        let state = TlsState::Tls {
+            version: "TLSv1_3".to_string(),
+            cipher: "TLS13_AES_256_GCM_SHA384".to_string(),
+            verified: false,
+        };
+        assert_eq!(
+            state.to_string(),
+            "TLSv1_3, TLS13_AES_256_GCM_SHA384, certificate NOT verified"
+        );
+    }
+}
@@ -1,42 +1,64 @@
+use crate::casemap::CaseMapping;
+use crate::channel::Channel;
 use crate::client::{Client, ServInfo};
 use crate::command::Cmd;
+use crate::editor::{Editor, KillRing};
+use crate::history::{History, Recall, Search, GLOBAL_HISTORY_LEN, TAB_HISTORY_LEN};
+use crate::input::Input;
 use crate::protocol::MsgTarget;
-use crate::{client, command, Config};
+use crate::{client, command, wrap, Config};
+use chrono::{DateTime, Local, Utc};
 use crossterm::cursor::MoveTo;
-use crossterm::event::KeyCode;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use crossterm::queue;
 use crossterm::style::Print;
 use crossterm::terminal::{Clear, ClearType};
 use std::cell::{Ref, RefCell};
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::io::Write;
 use std::rc::Rc;
+use std::time::Duration;
 use std::{fmt, io};
 use tokio::sync::mpsc::Receiver;
 
-pub async fn run(tui: UI, input_rx: Receiver<KeyCode>, clients: Vec<Client>) {
-    ui_loop(tui, clients, input_rx).await;
+/// Lines the mouse wheel scrolls by.
+const WHEEL_LINES: usize = 3;
+
+/// Run until the user quits, then save the input history.
+pub async fn run(tui: UI, input_rx: Receiver<Input>, clients: Vec<Client>) -> io::Result<()> {
+    ui_loop(tui.clone(), clients, input_rx).await;
+    tui.save_history()
 }
 
-async fn ui_loop(tui: UI, mut clients: Vec<Client>, mut input_rx: Receiver<KeyCode>) {
-    while let Some(cmd) = input_rx.recv().await {
-        match cmd {
-            KeyCode::Esc => {
-                break;
-            }
-            KeyCode::Char(c) => {
-                tui.push_input(c);
-            }
-            KeyCode::Enter => {
-                tui.commit_input(&mut clients);
-            }
-            KeyCode::Backspace => {
-                tui.pop_input();
-            }
-            KeyCode::Tab => {
-                tui.next_tab();
+async fn ui_loop(tui: UI, mut clients: Vec<Client>, mut input_rx: Receiver<Input>) {
+    while let Some(input) = input_rx.recv().await {
+        match input {
+            Input::Key(key) if tui.is_searching() => tui.search(key),
+            Input::Key(key) => {
+                let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+                match key.code {
+                    KeyCode::Esc => {
+                        break;
+                    }
+                    KeyCode::Enter => {
+                        tui.commit_input(&mut clients);
+                    }
+                    KeyCode::Tab => {
+                        tui.next_tab();
+                    }
+                    KeyCode::PageUp => tui.scroll(Scroll::PageUp),
+                    KeyCode::PageDown => tui.scroll(Scroll::PageDown),
+                    KeyCode::Home if ctrl => tui.scroll(Scroll::Top),
+                    KeyCode::End if ctrl => tui.scroll(Scroll::Bottom),
+                    KeyCode::Up => tui.recall(true, ctrl),
+                    KeyCode::Down => tui.recall(false, ctrl),
+                    KeyCode::Char('r') if ctrl => tui.start_search(),
+                    _ => tui.edit(key),
+                }
             }
-            _ => {}
+            Input::Scroll { up: true } => tui.scroll(Scroll::Up(WHEEL_LINES)),
+            Input::Scroll { up: false } => tui.scroll(Scroll::Down(WHEEL_LINES)),
+            Input::Resize { cols, rows } => tui.resize(cols, rows),
         }
 
         tui.draw();
@@ -46,28 +68,45 @@ async fn ui_loop(tui: UI, mut clients: Vec<Client>, mut input_rx: Receiver<KeyCo
 struct InnerUI {
     cur_tab: usize,
     tabs: Vec<Tab>,
+    /// By server, for telling whether two channel or query tabs are the same.
+    casemappings: HashMap<String, CaseMapping>,
+    /// What to call servers, by connection id
+    serv_names: HashMap<String, String>,
+    /// Connections made so far, for giving each its own id
+    connections: u32,
+    /// Terminal columns and rows
+    size: (u16, u16),
+    kills: KillRing,
+    /// Lines committed in any tab
+    history: History,
 }
 
 impl InnerUI {
-    fn new() -> Self {
+    fn new(size: (u16, u16), history: History) -> Self {
         Self {
+            size,
+            kills: KillRing::default(),
+            history,
             cur_tab: 0,
             tabs: vec![Tab::new(TabKind::Debug)],
+            casemappings: HashMap::new(),
+            serv_names: HashMap::new(),
+            connections: 0,
         }
     }
 
     fn dbg(&mut self, msg: &str) {
-        self.tabs[0].add_line(msg.to_string());
+        self.tabs[0].add_line(Utc::now(), msg.to_string());
     }
 
-    fn add_msg(&mut self, serv_name: &str, target: MsgTarget, msg: &str) {
+    fn add_msg(&mut self, time: DateTime<Utc>, serv_id: &str, target: MsgTarget, msg: &str) {
         let tab_id = match &target {
             MsgTarget::Chan(chan) => TabKind::Chan {
-                serv: serv_name.to_string(),
+                serv: serv_id.to_string(),
                 chan: chan.to_string(),
             },
             MsgTarget::User(nick) => TabKind::Query {
-                serv: serv_name.to_string(),
+                serv: serv_id.to_string(),
                 nick: nick.to_string(),
             },
             MsgTarget::Serv(serv) => TabKind::Serv {
@@ -76,9 +115,9 @@ impl InnerUI {
         };
 
         if let Some(tab) = self.find_tab_mut(&tab_id) {
-            tab.add_line(msg.to_string());
+            tab.add_line(time, msg.to_string());
         } else {
-            self.dbg(&format!("[{serv_name}] No tab found {target:?} ({msg})"));
+            self.dbg(&format!("[{serv_id}] No tab found {target:?} ({msg})"));
         }
     }
 
@@ -96,27 +135,97 @@ impl InnerUI {
     }
 
     fn find_tab_mut(&mut self, id: &TabKind) -> Option<&mut Tab> {
-        self.tabs.iter_mut().find(|tab| tab.id == *id)
+        let pos = self.tab_position(id)?;
+        Some(&mut self.tabs[pos])
     }
 
     fn tab_position(&self, id: &TabKind) -> Option<usize> {
-        self.tabs.iter().position(|tab| tab.id == *id)
+        self.tabs.iter().position(|tab| self.same_tab(&tab.id, id))
+    }
+
+    /// Channel names and nicks compare the way their server folds case.
+    fn same_tab(&self, a: &TabKind, b: &TabKind) -> bool {
+        let casemapping = |serv: &str| self.casemappings.get(serv).copied().unwrap_or_default();
+        match (a, b) {
+            (
+                TabKind::Chan { serv, chan: a },
+                TabKind::Chan {
+                    serv: serv_b,
+                    chan: b,
+                },
+            )
+            | (
+                TabKind::Query { serv, nick: a },
+                TabKind::Query {
+                    serv: serv_b,
+                    nick: b,
+                },
+            ) => serv == serv_b && casemapping(serv).eq(a, b),
+            _ => a == b,
+        }
     }
 
     pub fn next_tab(&mut self) {
         self.cur_tab = (self.cur_tab + 1) % self.tabs.len();
     }
 
-    pub fn push_input(&mut self, c: char) {
-        self.tabs[self.cur_tab].input.push(c);
+    pub fn edit(&mut self, key: KeyEvent) {
+        self.tabs[self.cur_tab].input.handle(key, &mut self.kills);
     }
 
-    pub fn pop_input(&mut self) {
-        self.tabs[self.cur_tab].input.pop();
+    /// Take the input out and remember it.
+    pub fn take_input(&mut self) -> String {
+        let tab = &mut self.tabs[self.cur_tab];
+        let input = tab.input.take();
+        tab.recall.reset();
+        tab.history.push(&input);
+        self.history.push(&input);
+        input
     }
 
-    pub fn take_input(&mut self) -> String {
-        std::mem::take(&mut self.tabs[self.cur_tab].input)
+    /// Show the line before (`older`) or after the one in the input, from the tab's history
+    /// or the global one.
+    pub fn recall(&mut self, older: bool, global: bool) {
+        let tab = &mut self.tabs[self.cur_tab];
+        let history = if global { &self.history } else { &tab.history };
+        let line = if older {
+            tab.recall
+                .older(history, global, tab.input.text())
+                .map(str::to_string)
+        } else {
+            tab.recall.newer(history, global)
+        };
+        if let Some(line) = line {
+            tab.input.set(line);
+        }
+    }
+
+    /// Handle a key while searching the global history.
+    pub fn search(&mut self, key: KeyEvent) {
+        let tab = &mut self.tabs[self.cur_tab];
+        let Some(search) = &mut tab.search else {
+            return;
+        };
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        match key.code {
+            KeyCode::Char('r') if ctrl => search.next(&self.history),
+            KeyCode::Char('g') if ctrl => tab.search = None,
+            KeyCode::Esc => tab.search = None,
+            KeyCode::Char(c) if !ctrl && !alt => search.push(c, &self.history),
+            KeyCode::Backspace => search.pop(&self.history),
+            // Anything else takes the match and carries on editing it.
+            _ => {
+                if let Some(found) = search.found(&self.history) {
+                    tab.input.set(found.to_string());
+                    tab.recall.reset();
+                }
+                tab.search = None;
+                if key.code != KeyCode::Enter {
+                    tab.input.handle(key, &mut self.kills);
+                }
+            }
+        }
     }
 }
 
@@ -127,10 +236,35 @@ pub struct UI {
 }
 
 impl UI {
-    pub fn new(config: Rc<RefCell<Config>>) -> Self {
-        Self {
-            inner: Rc::new(RefCell::new(InnerUI::new())),
+    /// A UI for a terminal of `size` columns and rows.
+    pub fn new(config: Rc<RefCell<Config>>, size: (u16, u16)) -> Self {
+        let ui = Self {
+            inner: Rc::new(RefCell::new(InnerUI::new(
+                size,
+                History::new(GLOBAL_HISTORY_LEN),
+            ))),
             config,
+        };
+        ui.load_history();
+        ui
+    }
+
+    /// Read the input history back from the history file, if there is one.
+    fn load_history(&self) {
+        let Some(path) = self.config.borrow().history_file.clone() else {
+            return;
+        };
+        match History::load(&path, GLOBAL_HISTORY_LEN) {
+            Ok(history) => self.inner.borrow_mut().history = history,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => self.dbg(&format!("Couldn't read {}: {e}", path.display())),
+        }
+    }
+
+    fn save_history(&self) -> io::Result<()> {
+        match &self.config.borrow().history_file {
+            Some(path) => self.inner.borrow().history.save(path),
+            None => Ok(()),
         }
     }
 
@@ -138,19 +272,144 @@ impl UI {
         self.inner.borrow_mut().dbg(msg);
     }
 
-    pub fn add_msg(&self, serv_name: &str, target: MsgTarget, msg: &str) {
-        self.inner.borrow_mut().add_msg(serv_name, target, msg);
+    pub fn add_msg(&self, serv_id: &str, target: MsgTarget, msg: &str) {
+        self.add_msg_at(Utc::now(), serv_id, target, msg);
+    }
+
+    /// Add a message that was sent at `time` rather than just now, e.g. from `server-time`.
+    pub fn add_msg_at(&self, time: DateTime<Utc>, serv_id: &str, target: MsgTarget, msg: &str) {
+        self.inner.borrow_mut().add_msg(time, serv_id, target, msg);
     }
 
-    pub fn add_serv_msg(&self, serv_name: &str, msg: &str) {
-        self.add_msg(serv_name, MsgTarget::Serv(serv_name.to_string()), msg);
+    pub fn add_serv_msg(&self, serv_id: &str, msg: &str) {
+        self.add_serv_msg_at(Utc::now(), serv_id, msg);
+    }
+
+    pub fn add_serv_msg_at(&self, time: DateTime<Utc>, serv_id: &str, msg: &str) {
+        self.add_msg_at(time, serv_id, MsgTarget::Serv(serv_id.to_string()), msg);
     }
 
     pub fn add_tab(&self, id: TabKind) {
         self.inner.borrow_mut().add_tab(id);
     }
 
-    fn current_tab(&self) -> Ref<Tab> {
+    /// Show how many messages flood control is holding back for a server.
+    pub fn set_queued(&self, serv_id: &str, len: usize) {
+        let id = TabKind::Serv {
+            serv: serv_id.to_string(),
+        };
+        if let Some(tab) = self.inner.borrow_mut().find_tab_mut(&id) {
+            tab.queued = len;
+        }
+    }
+
+    /// Format a time the way message timestamps are.
+    pub fn format_time(&self, time: DateTime<Utc>) -> String {
+        let time_format = &self.config.borrow().time_format;
+        time.with_timezone(&Local).format(time_format).to_string()
+    }
+
+    /// List the members of a channel in its tab.
+    pub fn show_names(&self, time: DateTime<Utc>, serv_id: &str, chan: &Channel) {
+        let members = chan
+            .members()
+            .map(|(nick, prefix)| match prefix {
+                Some(prefix) => format!("{prefix}{nick}"),
+                None => nick.to_string(),
+            })
+            .collect::<Vec<_>>();
+        let msg = format!(
+            "Users on {} (joined {}): {}",
+            chan.name,
+            self.format_time(chan.joined),
+            members.join(" ")
+        );
+        self.add_msg_at(time, serv_id, MsgTarget::Chan(chan.name.clone()), &msg);
+    }
+
+    /// Show the topic of a channel in its tab.
+    pub fn show_topic(&self, serv_id: &str, chan: &Channel) {
+        let msg = match &chan.topic {
+            Some(topic) => {
+                let mut msg = format!("Topic: {}", topic.text);
+                if let Some(setter) = &topic.setter {
+                    msg.push_str(&format!(" (set by {setter}"));
+                    if let Some(set_at) = topic.set_at {
+                        msg.push_str(&format!(" {}", self.format_time(set_at)));
+                    }
+                    msg.push(')');
+                }
+                msg
+            }
+            None => format!("No topic set for {}", chan.name),
+        };
+        self.add_msg(serv_id, MsgTarget::Chan(chan.name.clone()), &msg);
+    }
+
+    pub fn set_serv_name(&self, serv_id: &str, name: &str) {
+        self.inner
+            .borrow_mut()
+            .serv_names
+            .insert(serv_id.to_string(), name.to_string());
+    }
+
+    pub fn set_casemapping(&self, serv_id: &str, casemapping: CaseMapping) {
+        self.inner
+            .borrow_mut()
+            .casemappings
+            .insert(serv_id.to_string(), casemapping);
+    }
+
+    /// Show the last measured lag for a server, or nothing while disconnected.
+    pub fn set_lag(&self, serv_id: &str, lag: Option<Duration>) {
+        let id = TabKind::Serv {
+            serv: serv_id.to_string(),
+        };
+        if let Some(tab) = self.inner.borrow_mut().find_tab_mut(&id) {
+            tab.lag = lag;
+        }
+    }
+
+    pub fn has_query(&self, serv_id: &str, nick: &str) -> bool {
+        let id = TabKind::Query {
+            serv: serv_id.to_string(),
+            nick: nick.to_string(),
+        };
+        self.inner.borrow().tab_position(&id).is_some()
+    }
+
+    /// Follow a nick change with the query tab. Returns whether there was one to rename.
+    pub fn rename_query(&self, serv_id: &str, old: &str, new: &str) -> bool {
+        let id = TabKind::Query {
+            serv: serv_id.to_string(),
+            nick: old.to_string(),
+        };
+        match self.inner.borrow_mut().find_tab_mut(&id) {
+            Some(tab) => {
+                tab.id = TabKind::Query {
+                    serv: serv_id.to_string(),
+                    nick: new.to_string(),
+                };
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Channels with an open tab on the given server.
+    pub fn chans(&self, serv_id: &str) -> Vec<String> {
+        self.inner
+            .borrow()
+            .tabs
+            .iter()
+            .filter_map(|tab| match &tab.id {
+                TabKind::Chan { serv, chan } if serv == serv_id => Some(chan.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn current_tab(&self) -> Ref<'_, Tab> {
         let inner = self.inner.borrow();
         Ref::map(inner, |x| &x.tabs[x.cur_tab])
     }
@@ -159,6 +418,33 @@ impl UI {
         self.inner.borrow_mut().next_tab();
     }
 
+    /// Scroll the current tab.
+    pub fn scroll(&self, scroll: Scroll) {
+        let layout = self.layout();
+        let mut inner = self.inner.borrow_mut();
+        let cur_tab = inner.cur_tab;
+        inner.tabs[cur_tab].scroll(scroll, layout.height, |line| layout.rows(line));
+    }
+
+    /// Lay the tabs out again for a terminal of a new size. Tabs scrolled back keep their place.
+    pub fn resize(&self, cols: u16, rows: u16) {
+        self.inner.borrow_mut().size = (cols, rows);
+        let layout = self.layout();
+        for tab in &mut self.inner.borrow_mut().tabs {
+            tab.clamp_scroll(layout.height, &|line: &Line| layout.rows(line));
+        }
+    }
+
+    fn layout(&self) -> Layout {
+        let (cols, rows) = self.inner.borrow().size;
+        Layout {
+            width: cols as usize,
+            // Less the tab bar and the input line
+            height: (rows as usize).saturating_sub(2),
+            time_format: self.config.borrow().time_format.clone(),
+        }
+    }
+
     pub fn change_to_tab(&self, id: &TabKind) {
         if self.inner.borrow_mut().change_to_tab(id) {
             self.draw();
@@ -167,12 +453,28 @@ impl UI {
         }
     }
 
-    pub fn push_input(&self, c: char) {
-        self.inner.borrow_mut().push_input(c);
+    /// Edit the current tab's input.
+    pub fn edit(&self, key: KeyEvent) {
+        self.inner.borrow_mut().edit(key);
+    }
+
+    pub fn recall(&self, older: bool, global: bool) {
+        self.inner.borrow_mut().recall(older, global);
     }
 
-    pub fn pop_input(&self) {
-        self.inner.borrow_mut().pop_input();
+    pub fn start_search(&self) {
+        let mut inner = self.inner.borrow_mut();
+        let cur_tab = inner.cur_tab;
+        inner.tabs[cur_tab].search = Some(Search::default());
+    }
+
+    pub fn is_searching(&self) -> bool {
+        let inner = self.inner.borrow();
+        inner.tabs[inner.cur_tab].search.is_some()
+    }
+
+    pub fn search(&self, key: KeyEvent) {
+        self.inner.borrow_mut().search(key);
     }
 
     fn take_input(&self) -> String {
@@ -185,43 +487,92 @@ impl UI {
             Err(e) => self.dbg(&format!("Command parse error: {e}")),
             Ok(cmd) => match cmd {
                 Cmd::Connect(addr) => {
-                    self.dbg(&format!("Connecting to {addr}"));
+                    let sasl = {
+                        let config = self.config.borrow();
+                        match config.sasl_for(&addr.host) {
+                            Some(sasl) => {
+                                match sasl.refusal(addr.tls, addr.verify, config.sasl_insecure) {
+                                    None => Some(sasl.clone()),
+                                    Some(reason) if sasl.required => {
+                                        self.dbg(&format!(
+                                            "Not connecting to {}: {reason} and SASL is required",
+                                            addr.host
+                                        ));
+                                        return;
+                                    }
+                                    Some(reason) => {
+                                        self.dbg(&format!(
+                                            "Not using SASL with {}: {reason}",
+                                            addr.host
+                                        ));
+                                        None
+                                    }
+                                }
+                            }
+                            None => None,
+                        }
+                    };
+                    self.dbg(&format!("Connecting to {}:{}", addr.host, addr.port));
+                    let serv_id = {
+                        let mut inner = self.inner.borrow_mut();
+                        inner.connections += 1;
+                        format!("{}/{}", addr.host, inner.connections)
+                    };
                     let serv_info = ServInfo {
-                        addr,
-                        port: 6667,
+                        id: serv_id.clone(),
+                        name: addr.name,
+                        addr: addr.host,
+                        port: addr.port,
+                        tls: addr.tls,
+                        tls_verify: addr.verify,
+                        tls_cert: self.config.borrow().tls_cert.clone(),
+                        sasl,
                         nick: self.config.borrow().nick.clone(),
+                        alt_nicks: self.config.borrow().alt_nicks.clone(),
                         user: self.config.borrow().user.clone(),
                         real: self.config.borrow().real.clone(),
+                        backoff: self.config.borrow().backoff.clone(),
+                        flood: self.config.borrow().flood.clone(),
+                        keepalive: self.config.borrow().keepalive.clone(),
                     };
                     self.dbg(&format!("{serv_info:?}"));
 
-                    let serv_name = serv_info.name().to_string();
-
                     let tab_id = TabKind::Serv {
-                        serv: serv_name.clone(),
+                        serv: serv_id.clone(),
                     };
                     self.add_tab(tab_id.clone());
                     self.change_to_tab(&tab_id);
 
                     let (client, ev_rx, dbg_rx) = Client::new(serv_info);
+                    self.set_serv_name(&serv_id, &client.name());
                     tokio::task::spawn_local(client::handle_network_events(
                         ev_rx,
                         dbg_rx,
                         self.clone(),
-                        serv_name,
+                        client.clone(),
                     ));
                     clients.push(client);
                 }
-                Cmd::Join(chan) => {
+                Cmd::Join(chans) => {
                     let tab_id = self.current_tab().id.clone();
                     match tab_id {
                         TabKind::Serv { serv } => {
-                            self.dbg(&format!("Joining {chan} on {serv}"));
-                            if let Some(client) = clients.iter().find(|c| c.name == serv) {
-                                client.join(&chan);
-                                let tab_id = TabKind::Chan { serv, chan };
-                                self.add_tab(tab_id.clone());
-                                self.change_to_tab(&tab_id);
+                            let names = chans.iter().map(|(chan, _)| chan.as_str());
+                            let names = names.collect::<Vec<_>>().join(",");
+                            self.dbg(&format!("Joining {names} on {serv}"));
+                            if let Some(client) = clients.iter().find(|c| c.id == serv) {
+                                if let Err(e) = client.join(&chans) {
+                                    self.dbg(&format!("Cannot join {names:?}: {e}"));
+                                    return;
+                                }
+                                for (chan, _) in chans {
+                                    let tab_id = TabKind::Chan {
+                                        serv: serv.clone(),
+                                        chan,
+                                    };
+                                    self.add_tab(tab_id.clone());
+                                    self.change_to_tab(&tab_id);
+                                }
                             } else {
                                 self.dbg(&format!("No client found for server {serv}"));
                             }
@@ -233,12 +584,33 @@ impl UI {
                 }
                 Cmd::Quit(msg) => {
                     if let Some(client) = self.find_client_for_current_tab(clients) {
-                        client.quit(&msg);
+                        if let Err(e) = client.quit(&msg) {
+                            self.dbg(&format!("Cannot quit: {e}"));
+                        }
+                    }
+                }
+                Cmd::Reconnect => {
+                    if let Some(client) = self.find_client_for_current_tab(clients) {
+                        if let Err(e) = client.reconnect() {
+                            self.dbg(&format!("Cannot reconnect: {e}"));
+                        }
                     }
                 }
                 Cmd::Nick(nick) => {
-                    if let Some(client) = self.find_client_for_current_tab_mut(clients) {
-                        client.nick(&nick);
+                    if let Some(client) = self.find_client_for_current_tab(clients) {
+                        if let Err(e) = client.nick(&nick) {
+                            self.dbg(&format!("Cannot change nick to {nick:?}: {e}"));
+                            return;
+                        }
+                        match client.nicklen() {
+                            Some(len) if nick.chars().count() > len => {
+                                let msg = format!(
+                                    "Nicks are at most {len} characters here, {nick:?} may get cut"
+                                );
+                                self.add_serv_msg(&client.id, &msg);
+                            }
+                            _ => {}
+                        }
                     }
                 }
                 Cmd::Msg(msg) => {
@@ -261,16 +633,55 @@ impl UI {
                             None
                         }
                     } {
-                        if let Some(client) = clients.iter().find(|c| c.name == *serv) {
+                        if let Some(client) = clients.iter().find(|c| c.id == *serv) {
                             // FIXME message formatting sprawled in ui and client modules
-                            client.privmsg(msg_target.target(), &msg);
-                            let msg = format!("<{}> {msg}", &client.cur_nick);
-                            self.add_msg(&client.name, msg_target, &msg);
+                            match client.privmsg(msg_target.target(), &msg) {
+                                Ok(pieces) => {
+                                    for piece in pieces {
+                                        let msg = format!("<{}> {piece}", client.cur_nick());
+                                        self.add_msg(&client.id, msg_target.clone(), &msg);
+                                    }
+                                }
+                                Err(e) => self.dbg(&format!("Cannot send message: {e}")),
+                            }
                         } else {
                             self.dbg(&format!("No client found for server {serv}"));
                         }
                     }
                 }
+                Cmd::Names | Cmd::Topic(_) => {
+                    let TabKind::Chan { serv, chan } = self.current_tab().id.clone() else {
+                        self.dbg(&format!("{cmd:?} command outside of a channel tab"));
+                        return;
+                    };
+                    let Some(client) = clients.iter().find(|c| c.id == serv) else {
+                        self.dbg(&format!("No client found for server {serv}"));
+                        return;
+                    };
+                    if let Cmd::Topic(Some(topic)) = &cmd {
+                        if let Err(e) = client.topic(&chan, topic) {
+                            self.dbg(&format!("Cannot set topic: {e}"));
+                            return;
+                        }
+                        match client.topiclen() {
+                            Some(len) if topic.chars().count() > len => {
+                                let msg = format!(
+                                    "Topics are at most {len} characters here, it may get cut"
+                                );
+                                self.add_msg(&serv, MsgTarget::Chan(chan), &msg);
+                            }
+                            _ => {}
+                        }
+                        return;
+                    }
+                    match client.channel(&chan) {
+                        Some(chan) if cmd == Cmd::Names => {
+                            self.show_names(Utc::now(), &serv, &chan)
+                        }
+                        Some(chan) => self.show_topic(&serv, &chan),
+                        None => self.add_msg(&serv, MsgTarget::Chan(chan), "Not in this channel"),
+                    }
+                }
                 Cmd::Unsupported { cmd, rest } => {
                     self.dbg(&format!("Unsupported command: {cmd} {rest}"));
                 }
@@ -286,69 +697,70 @@ impl UI {
             TabKind::Query { serv, .. } => serv,
             _ => return None,
         };
-        clients.iter().find(|c| c.name == *serv)
-    }
-
-    fn find_client_for_current_tab_mut<'a>(
-        &self,
-        clients: &'a mut [Client],
-    ) -> Option<&'a mut Client> {
-        let tab_id = &self.current_tab().id;
-        let serv = match tab_id {
-            TabKind::Serv { serv } => serv,
-            TabKind::Chan { serv, .. } => serv,
-            TabKind::Query { serv, .. } => serv,
-            _ => return None,
-        };
-        clients.iter_mut().find(|c| c.name == *serv)
+        clients.iter().find(|c| c.id == *serv)
     }
 
     pub fn draw(&self) {
-        let inner = self.inner.borrow();
+        let layout = self.layout();
+        let mut inner = self.inner.borrow_mut();
         // Draw tabs on top
         queue!(io::stdout(), MoveTo(0, 0), Clear(ClearType::CurrentLine),)
             .expect("failed to draw tab");
         for (i, tab) in inner.tabs.iter().enumerate() {
-            tab.draw(i == inner.cur_tab);
+            let name = match &tab.id {
+                TabKind::Serv { serv } => inner.serv_names.get(serv).unwrap_or(serv).clone(),
+                id => id.to_string(),
+            };
+            tab.draw(&name, i == inner.cur_tab);
         }
 
-        // Draw tab content
+        // Draw tab content, lined up at the bottom
         let tab = &inner.tabs[inner.cur_tab];
-        let (_, rows) = crossterm::terminal::size().expect("failed to get terminal size");
-
-        // Clear initial empty lines if there are fewer lines of text than there are rows
-        if tab.lines.len() < rows as usize - 1 {
-            for y in 1..=rows - 2 - tab.lines.len() as u16 {
-                queue!(io::stdout(), MoveTo(0, y), Clear(ClearType::CurrentLine),)
-                    .expect("failed to draw tab content");
-            }
+        let rows = layout.height as u16 + 2;
+        let (mut texts, newer) = tab.visible(layout.height, |line| layout.rows(line));
+        if newer > 0 {
+            texts.push(format!("-- more ({newer}) --"));
         }
-
-        // Draw lines of text
-        let mut y = rows - 2;
-        let messages = tab.lines.iter().rev().take(rows as usize - 1).peekable();
-        for message in messages {
+        let first = rows.saturating_sub(1 + texts.len() as u16).max(1);
+        for y in 1..first {
+            queue!(io::stdout(), MoveTo(0, y), Clear(ClearType::CurrentLine))
+                .expect("failed to draw tab content");
+        }
+        for (y, text) in (first..).zip(texts) {
             queue!(
                 io::stdout(),
                 MoveTo(0, y),
                 Clear(ClearType::CurrentLine),
-                MoveTo(0, y),
-                Print(message),
+                Print(text),
             )
             .expect("failed to draw tab content");
-            if y == 1 {
-                break;
-            }
-            y -= 1;
         }
 
-        // Draw input buffer
+        // Draw input buffer, with the terminal's cursor as ours
+        let cur_tab = inner.cur_tab;
+        let inner = &mut *inner;
+        let tab = &mut inner.tabs[cur_tab];
+        let search;
+        let (input, cursor) = match &tab.search {
+            Some(s) => {
+                let prompt = format!(
+                    "({}reverse-i-search)`{}'",
+                    if s.failed() { "failed " } else { "" },
+                    s.query()
+                );
+                let cursor = wrap::width(&prompt) - 1;
+                let text = format!("{prompt}: {}", s.found(&inner.history).unwrap_or(""));
+                search = wrap::wrap(&text, layout.width, 0).swap_remove(0);
+                (search.as_str(), cursor.min(layout.width.saturating_sub(1)))
+            }
+            None => tab.input.view(layout.width),
+        };
         queue!(
             io::stdout(),
             MoveTo(0, rows - 1),
             Clear(ClearType::CurrentLine),
-            MoveTo(0, rows - 1),
-            Print(&tab.input),
+            Print(input),
+            MoveTo(cursor as u16, rows - 1),
         )
         .expect("failed to draw input buffer");
 
@@ -360,31 +772,249 @@ struct Tab {
     /// Identifier for the tab
     id: TabKind,
     /// Content of the input buffer associated with this tab
-    input: String,
+    input: Editor,
+    /// Lines committed in this tab
+    history: History,
+    recall: Recall,
+    /// Ctrl-R search of the global history, while on
+    search: Option<Search>,
     /// Lines of output associated with this tab
-    lines: VecDeque<String>,
+    lines: VecDeque<Line>,
+    /// Outgoing messages waiting on flood control, for server tabs
+    queued: usize,
+    /// Round trip time of the last ping, for server tabs
+    lag: Option<Duration>,
+    /// The bottom row on screen while scrolled back, as a line and a row of it once wrapped.
+    /// `None` follows new lines.
+    scroll: Option<(usize, usize)>,
+}
+
+/// Ways to move through a tab's lines.
+#[derive(Debug, Clone, Copy)]
+pub enum Scroll {
+    /// Towards older lines, by so many rows.
+    Up(usize),
+    Down(usize),
+    /// By a screen, less a row of context.
+    PageUp,
+    PageDown,
+    Top,
+    Bottom,
+}
+
+/// How lines are laid out on screen.
+struct Layout {
+    width: usize,
+    /// Rows between the tab bar and the input line
+    height: usize,
+    time_format: String,
+}
+
+impl Layout {
+    /// A line with its timestamp, wrapped to the width. Continuation rows line up with the
+    /// message after `<nick>`, or else with the text after the timestamp.
+    fn rows(&self, line: &Line) -> Vec<String> {
+        let time = line.time.with_timezone(&Local).format(&self.time_format);
+        let prefix = format!("{time} ");
+        let body = line
+            .text
+            .strip_prefix('<')
+            .and_then(|rest| rest.find("> "))
+            .map_or(0, |end| wrap::width(&line.text[..end + 3]));
+        let indent = wrap::width(&prefix) + body;
+        wrap::wrap(&(prefix + &line.text), self.width, indent)
+    }
+}
+
+struct Line {
+    /// When the line was sent, according to the server if it told us
+    time: DateTime<Utc>,
+    text: String,
 }
 
 impl Tab {
     pub fn new(id: TabKind) -> Self {
         Self {
             id,
-            input: String::with_capacity(256),
+            input: Editor::default(),
+            history: History::new(TAB_HISTORY_LEN),
+            recall: Recall::default(),
+            search: None,
             lines: VecDeque::new(),
+            queued: 0,
+            lag: None,
+            scroll: None,
+        }
+    }
+
+    pub fn add_line(&mut self, time: DateTime<Utc>, text: String) {
+        self.lines.push_back(Line { time, text });
+    }
+
+    /// The last row of the last line.
+    fn last_row<F: Fn(&Line) -> Vec<String>>(&self, rows: &F) -> Option<(usize, usize)> {
+        let line = self.lines.len().checked_sub(1)?;
+        Some((line, rows(&self.lines[line]).len() - 1))
+    }
+
+    /// Go back `n` rows from `(line, row)`, stopping at the first. Returns where that is and how
+    /// many rows it went.
+    fn rows_back<F: Fn(&Line) -> Vec<String>>(
+        &self,
+        (mut line, row): (usize, usize),
+        mut n: usize,
+        rows: &F,
+    ) -> ((usize, usize), usize) {
+        let mut row = row.min(rows(&self.lines[line]).len() - 1);
+        let mut moved = 0;
+        while n > 0 {
+            if row >= n {
+                row -= n;
+                moved += n;
+                break;
+            }
+            if line == 0 {
+                moved += row;
+                row = 0;
+                break;
+            }
+            n -= row + 1;
+            moved += row + 1;
+            line -= 1;
+            row = rows(&self.lines[line]).len() - 1;
+        }
+        ((line, row), moved)
+    }
+
+    /// Go forward `n` rows from `(line, row)`, stopping at the last.
+    fn rows_forward<F: Fn(&Line) -> Vec<String>>(
+        &self,
+        (mut line, row): (usize, usize),
+        mut n: usize,
+        rows: &F,
+    ) -> (usize, usize) {
+        let count = |line: usize| rows(&self.lines[line]).len();
+        let mut row = row.min(count(line) - 1);
+        while n > 0 {
+            let left = count(line) - 1 - row;
+            if left >= n {
+                row += n;
+                break;
+            }
+            if line == self.lines.len() - 1 {
+                row = count(line) - 1;
+                break;
+            }
+            n -= left + 1;
+            line += 1;
+            row = 0;
+        }
+        (line, row)
+    }
+
+    /// Scroll, given `height` rows to show lines laid out by `rows`.
+    fn scroll<F: Fn(&Line) -> Vec<String>>(&mut self, scroll: Scroll, height: usize, rows: F) {
+        let Some(last) = self.last_row(&rows) else {
+            return;
+        };
+        let bottom = self.scroll.unwrap_or(last);
+        let page = height.saturating_sub(2).max(1);
+        self.scroll = match scroll {
+            Scroll::Up(n) => Some(self.rows_back(bottom, n, &rows).0),
+            Scroll::Down(n) => Some(self.rows_forward(bottom, n, &rows)),
+            Scroll::PageUp => Some(self.rows_back(bottom, page, &rows).0),
+            Scroll::PageDown => Some(self.rows_forward(bottom, page, &rows)),
+            Scroll::Top => Some((0, 0)),
+            Scroll::Bottom => None,
+        };
+        self.clamp_scroll(height, &rows);
+    }
+
+    /// Keep the screen full while scrolled back, and follow new lines again once at the bottom.
+    fn clamp_scroll<F: Fn(&Line) -> Vec<String>>(&mut self, height: usize, rows: &F) {
+        let (Some(bottom), Some(last)) = (self.scroll, self.last_row(rows)) else {
+            return;
+        };
+        // Everything fits, so there's nothing to scroll.
+        if self.rows_back(last, height, rows).1 < height {
+            self.scroll = None;
+            return;
+        }
+        // The row may be past the end of its line after a resize.
+        let bottom = self.rows_back(bottom, 0, rows).0;
+        // A row goes to the "more" indicator.
+        let above = height.saturating_sub(2);
+        let bottom = if self.rows_back(bottom, above, rows).1 < above {
+            self.rows_forward((0, 0), above, rows)
+        } else {
+            bottom
+        };
+        self.scroll = (bottom < last).then_some(bottom);
+    }
+
+    /// Rows that fit in `height`, oldest first, and how many newer ones are below them.
+    fn visible<F: Fn(&Line) -> Vec<String>>(&self, height: usize, rows: F) -> (Vec<String>, usize) {
+        let Some(last) = self.last_row(&rows) else {
+            return (vec![], 0);
+        };
+        let (bottom, fit) = match self.scroll {
+            Some(bottom) => (bottom, height.saturating_sub(1)),
+            None => (last, height),
+        };
+        let mut shown = vec![];
+        for line in (0..=bottom.0).rev() {
+            if shown.len() == fit {
+                break;
+            }
+            let mut line_rows = rows(&self.lines[line]);
+            if line == bottom.0 {
+                line_rows.truncate(bottom.1 + 1);
+            }
+            for row in line_rows.into_iter().rev() {
+                if shown.len() == fit {
+                    break;
+                }
+                shown.push(row);
+            }
         }
+        shown.reverse();
+        let newer = match self.scroll {
+            Some((line, row)) => {
+                let rest = rows(&self.lines[line]).len().saturating_sub(row + 1);
+                rest + self
+                    .lines
+                    .range(line + 1..)
+                    .map(|line| rows(line).len())
+                    .sum::<usize>()
+            }
+            None => 0,
+        };
+        (shown, newer)
     }
 
-    pub fn add_line(&mut self, line: String) {
-        self.lines.push_back(line);
+    fn label(&self, name: &str) -> String {
+        let mut status = vec![];
+        if let Some(lag) = self.lag {
+            status.push(format!("lag {:.1}s", lag.as_secs_f64()));
+        }
+        if self.queued > 0 {
+            status.push(format!("queued {}", self.queued));
+        }
+        if status.is_empty() {
+            name.to_string()
+        } else {
+            format!("{name} ({})", status.join(", "))
+        }
     }
 
-    pub fn draw(&self, is_active: bool) {
+    /// Draw the tab's label, `name` standing in for its id.
+    pub fn draw(&self, name: &str, is_active: bool) {
         queue!(
             io::stdout(),
             Print(if is_active {
-                format!("[{}]", &self.id)
+                format!("[{}]", self.label(name))
             } else {
-                format!(" {} ", &self.id)
+                format!(" {} ", self.label(name))
             })
         )
         .expect("failed to draw tab");
@@ -409,3 +1039,181 @@ impl fmt::Display for TabKind {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tab(lines: &[&str]) -> Tab {
+        let mut tab = Tab::new(TabKind::Debug);
+        for line in lines {
+            tab.add_line(DateTime::UNIX_EPOCH, line.to_string());
+        }
+        tab
+    }
+
+    fn strings(strs: &[&str]) -> Vec<String> {
+        strs.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn numbered(lines: usize) -> Tab {
+        let lines = (0..lines).map(|n| n.to_string()).collect::<Vec<_>>();
+        tab(&lines.iter().map(String::as_str).collect::<Vec<_>>())
+    }
+
+    /// Four columns wide
+    fn rows(line: &Line) -> Vec<String> {
+        wrap::wrap(&line.text, 4, 0)
+    }
+
+    /// The rows shown in 4 rows of height, and how many newer ones there are.
+    fn visible(tab: &Tab) -> (Vec<String>, usize) {
+        tab.visible(4, rows)
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut tab = numbered(10);
+        assert_eq!(visible(&tab), (strings(&["6", "7", "8", "9"]), 0));
+
+        tab.scroll(Scroll::Up(2), 4, rows);
+        assert_eq!(visible(&tab), (strings(&["5", "6", "7"]), 2));
+        // New lines don't move the view while scrolled back.
+        tab.add_line(DateTime::UNIX_EPOCH, "10".to_string());
+        assert_eq!(visible(&tab), (strings(&["5", "6", "7"]), 3));
+
+        tab.scroll(Scroll::Top, 4, rows);
+        assert_eq!(visible(&tab), (strings(&["0", "1", "2"]), 8));
+        tab.scroll(Scroll::Up(1), 4, rows);
+        assert_eq!(visible(&tab), (strings(&["0", "1", "2"]), 8));
+        tab.scroll(Scroll::PageDown, 4, rows);
+        assert_eq!(visible(&tab), (strings(&["2", "3", "4"]), 6));
+
+        tab.scroll(Scroll::Down(100), 4, rows);
+        assert_eq!(tab.scroll, None);
+        tab.add_line(DateTime::UNIX_EPOCH, "11".to_string());
+        assert_eq!(visible(&tab), (strings(&["8", "9", "10", "11"]), 0));
+    }
+
+    #[test]
+    fn test_scroll_short_tab() {
+        let mut tab = numbered(3);
+        tab.scroll(Scroll::Up(5), 4, rows);
+        assert_eq!(tab.scroll, None);
+        assert_eq!(visible(&tab), (strings(&["0", "1", "2"]), 0));
+    }
+
+    #[test]
+    fn test_scroll_wrapped() {
+        let mut tab = tab(&["a", "bbbbbb", "c", "dd dd", "e"]);
+        assert_eq!(visible(&tab), (strings(&["c", "dd", "dd", "e"]), 0));
+        // By rows rather than lines
+        tab.scroll(Scroll::Up(2), 4, rows);
+        assert_eq!(visible(&tab), (strings(&["bb", "c", "dd"]), 2));
+        tab.scroll(Scroll::Top, 4, rows);
+        assert_eq!(visible(&tab), (strings(&["a", "bbbb", "bb"]), 4));
+    }
+
+    /// What the current tab shows with the UI's idea of the terminal size.
+    fn shown(tui: &UI) -> (Vec<String>, usize) {
+        let layout = tui.layout();
+        let inner = tui.inner.borrow();
+        inner.tabs[inner.cur_tab].visible(layout.height, |line| layout.rows(line))
+    }
+
+    #[test]
+    fn test_resize() {
+        let tui = UI::new(Rc::new(RefCell::new(Config::default())), (40, 6));
+        for n in 0..10 {
+            tui.dbg(&format!("line {n} with some words"));
+        }
+        tui.scroll(Scroll::Up(2));
+        let (rows, newer) = shown(&tui);
+        assert!(rows.last().unwrap().ends_with("line 7 with some words"));
+        assert_eq!(newer, 2);
+
+        // Three rows a line now, and still down to line 7.
+        tui.resize(16, 6);
+        let (rows, newer) = shown(&tui);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], "      with some");
+        assert_eq!(rows[1], "      words");
+        assert!(rows[2].ends_with("line 7"));
+        assert_eq!(newer, 2 + 2 * 3);
+
+        // Tall enough that line 7 would leave the top of the screen empty
+        tui.resize(16, 30);
+        let (rows, newer) = shown(&tui);
+        assert_eq!(rows.len(), 27);
+        assert!(rows[0].ends_with("line 0"));
+        assert_eq!(newer, 3);
+
+        // Everything fits
+        tui.resize(40, 30);
+        assert_eq!(shown(&tui).0.len(), 10);
+        assert_eq!(tui.inner.borrow().tabs[0].scroll, None);
+    }
+
+    #[test]
+    fn test_history() {
+        let tui = UI::new(Rc::new(RefCell::new(Config::default())), (40, 6));
+        let mut inner = tui.inner.borrow_mut();
+        inner.add_tab(TabKind::Serv {
+            serv: "a".to_string(),
+        });
+        let typed = |inner: &mut InnerUI, text: &str| {
+            for c in text.chars() {
+                inner.edit(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+            }
+        };
+        let input = |inner: &InnerUI| inner.tabs[inner.cur_tab].input.text().to_string();
+        typed(&mut inner, "in debug");
+        inner.take_input();
+        inner.next_tab();
+        typed(&mut inner, "in serv");
+        inner.take_input();
+
+        typed(&mut inner, "draft");
+        inner.recall(true, false);
+        assert_eq!(input(&inner), "in serv");
+        inner.recall(true, false);
+        assert_eq!(input(&inner), "in serv");
+        inner.recall(true, true);
+        inner.recall(true, true);
+        assert_eq!(input(&inner), "in debug");
+        inner.recall(false, true);
+        inner.recall(false, true);
+        assert_eq!(input(&inner), "draft");
+
+        inner.tabs[1].search = Some(Search::default());
+        for c in "deb".chars() {
+            inner.search(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+        inner.search(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+        assert!(inner.tabs[1].search.is_none());
+        assert_eq!(input(&inner), "in debug");
+    }
+
+    #[test]
+    fn test_layout() {
+        let layout = Layout {
+            width: 24,
+            height: 10,
+            time_format: "%H:%M".to_string(),
+        };
+        let line = Line {
+            time: DateTime::UNIX_EPOCH,
+            text: "<bob> the quick brown fox".to_string(),
+        };
+        let time = DateTime::<Utc>::UNIX_EPOCH
+            .with_timezone(&Local)
+            .format("%H:%M");
+        assert_eq!(
+            layout.rows(&line),
+            vec![
+                format!("{time} <bob> the quick"),
+                "            brown fox".to_string(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,93 @@
+/// Soft wrapping by display width
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Columns `text` takes up on screen. Wide characters take two, combining marks none.
+pub fn width(text: &str) -> usize {
+    text.width()
+}
+
+/// Break `text` into rows of at most `width` columns, after spaces where possible. Rows after the
+/// first start with `indent` spaces, unless that would leave less than half the width.
+pub fn wrap(text: &str, width: usize, indent: usize) -> Vec<String> {
+    let indent = if indent * 2 > width { 0 } else { indent };
+    let mut rows = vec![];
+    let mut row = String::new();
+    let mut row_width = 0;
+    // Where the row starts: 0 on the first row, `indent` after.
+    let mut start = 0;
+    // Byte offset and width of the row after its last space, where it can break.
+    let mut space = None;
+    for c in text.chars() {
+        let w = c.width().unwrap_or(0);
+        // A space that doesn't fit is a break on its own.
+        if c == ' ' && row_width + w > width {
+            space = Some((row.len(), row_width));
+        }
+        while row_width + w > width && row_width > start {
+            // Move the word after the last space down, or cut it if there's none.
+            let (rest, rest_width) = match space.take() {
+                Some((offset, space_width)) => (row.split_off(offset), row_width - space_width),
+                None => (String::new(), 0),
+            };
+            rows.push(row.trim_end().to_string());
+            row = " ".repeat(indent) + &rest;
+            row_width = indent + rest_width;
+            start = indent;
+        }
+        // Spaces don't start a continuation row.
+        if c == ' ' && !rows.is_empty() && row_width == start {
+            continue;
+        }
+        row.push(c);
+        row_width += w;
+        if c == ' ' {
+            space = Some((row.len(), row_width));
+        }
+    }
+    rows.push(row);
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short() {
+        assert_eq!(wrap("hello", 10, 2), vec!["hello"]);
+        assert_eq!(wrap("", 10, 2), vec![""]);
+    }
+
+    #[test]
+    fn test_spaces() {
+        assert_eq!(
+            wrap("<bob> the quick brown fox", 12, 6),
+            vec!["<bob> the", "      quick", "      brown", "      fox"]
+        );
+        assert_eq!(wrap("aaa bbb ccc", 7, 0), vec!["aaa bbb", "ccc"]);
+    }
+
+    #[test]
+    fn test_long_word() {
+        assert_eq!(wrap("abcdefghij", 4, 1), vec!["abcd", " efg", " hij"]);
+        assert_eq!(wrap("ab cdefghij", 4, 0), vec!["ab", "cdef", "ghij"]);
+    }
+
+    #[test]
+    fn test_unicode_width() {
+        // Two columns each
+        assert_eq!(wrap("日本語の文", 6, 0), vec!["日本語", "の文"]);
+        assert_eq!(wrap("🦀🦀🦀", 5, 0), vec!["🦀🦀", "🦀"]);
+        // Combining marks stay with their letter.
+        assert_eq!(
+            wrap("e\u{301}e\u{301}e\u{301}", 2, 0),
+            vec!["e\u{301}e\u{301}", "e\u{301}"]
+        );
+        assert_eq!(width("日本 e\u{301}"), 6);
+    }
+
+    #[test]
+    fn test_indent_too_wide() {
+        assert_eq!(wrap("aaaa bbbb", 6, 4), vec!["aaaa", "bbbb"]);
+    }
+}